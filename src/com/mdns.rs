@@ -1,143 +1,185 @@
-use local_ip_address::local_ip;
-use log::{debug, info};
-use mdns_sd::{Receiver as MdnsReceiver, ServiceDaemon, ServiceEvent};
-use std::net::SocketAddrV4;
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
-use std::thread::{Builder, JoinHandle};
-use std::time::{Duration, Instant};
+use log::{debug, info, warn};
+use mdns_sd::{DaemonEvent, ResolvedService, ScopedIp, ServiceDaemon, ServiceEvent};
+use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::sync::mpsc::Sender;
+use std::thread::JoinHandle;
 
-const SERVICE_TYPE: &str = "_audio-jsonrpc-websocket._tcp.local.";
+use crate::DiscoveredDevice;
+use crate::common::worker;
 
-enum Command {
-    Quit,
+const TXT_NAME: &str = "name";
+const TXT_FIRMWARE_VERSION: &str = "fw";
+const TXT_PROTOCOL_VERSION: &str = "protocol";
+const TXT_CAPABILITIES: &str = "caps";
+
+pub(crate) enum Event {
+    Found(DiscoveredDevice),
+    Lost(String),
+    Failed(String),
 }
 
 pub(crate) struct Mdns {
-    handle: Option<JoinHandle<()>>,
-    sender: Sender<Command>,
-    receiver: Receiver<SocketAddrV4>,
+    daemon: ServiceDaemon,
+    service_type: String,
+    handles: Vec<JoinHandle<()>>,
 }
 
 impl Mdns {
-    pub(crate) fn new() -> Self {
-        let (sender, rx) = mpsc::channel();
-        let (tx, receiver) = mpsc::channel();
-        Self {
-            handle: Some(
-                Builder::new()
-                    .name("audio:mdns".into())
-                    .spawn(move || Self::thread(tx, rx))
-                    .unwrap(),
-            ),
-            sender,
-            receiver,
-        }
-    }
-
-    pub(crate) fn recv_timeout(&self, dur: Duration) -> Result<SocketAddrV4, RecvTimeoutError> {
-        self.receiver.recv_timeout(dur)
-    }
-
-    fn thread(tx: Sender<SocketAddrV4>, rx: Receiver<Command>) {
-        let mut daemon = None;
-        let mut receiver = None;
-        let mut ip_addr = None;
-        let mut ip_time = Instant::now();
+    pub(crate) fn new(service_type: &str, tx: Sender<super::Command>) -> Result<Self, String> {
+        // the daemon browses on all interfaces and checks for added or removed interfaces itself
+        let daemon = ServiceDaemon::new().map_err(|e| format!("mDNS daemon failed: {e}"))?;
+        // from here on dropping self shuts the daemon down again
+        let mut mdns = Self {
+            daemon,
+            service_type: service_type.to_owned(),
+            handles: Vec::new(),
+        };
+        let monitor = mdns
+            .daemon
+            .monitor()
+            .map_err(|e| format!("mDNS monitor failed: {e}"))?;
+        let receiver = mdns
+            .daemon
+            .browse(service_type)
+            .map_err(|e| format!("mDNS browse failed: {e}"))?;
+        debug!("mDNS daemon started");
 
-        debug!("mDNS thread started");
-
-        loop {
-            match rx.recv_timeout(Duration::from_millis(10)) {
-                Ok(Command::Quit) => {
-                    debug!("mDNS thread received Quit");
-                    Self::stop(&mut daemon, &mut receiver);
-                    break;
-                }
-                Err(_) => {}
-            }
-
-            if ip_time.elapsed() >= Duration::from_secs(1) {
-                match local_ip() {
-                    Ok(ip) => {
-                        if let Some(old_ip) = ip_addr {
-                            if ip != old_ip {
-                                info!("New IP: {ip:?}");
-                                ip_addr = Some(ip);
-                                Self::stop(&mut daemon, &mut receiver);
-                                Self::start(&mut daemon, &mut receiver);
+        // both threads block on the daemon's channels, they end with stop_browse and shutdown
+        let failed = tx.clone();
+        let browse = worker::spawn(
+            "audio:mdns",
+            move || {
+                while let Ok(event) = receiver.recv() {
+                    match event {
+                        ServiceEvent::ServiceResolved(info) => {
+                            let device = Self::device(&info);
+                            debug!(
+                                "mDNS service resolved: {} {} {:?} on {:?}",
+                                device.fullname,
+                                device.hostname,
+                                device.addresses,
+                                device.interfaces,
+                            );
+                            if !device.addresses.is_empty()
+                                && tx.send(super::Command::Mdns(Event::Found(device))).is_err()
+                            {
+                                break;
                             }
-                        } else {
-                            info!("New IP: {ip:?}");
-                            ip_addr = Some(ip);
-                            Self::start(&mut daemon, &mut receiver);
                         }
-                    }
-                    Err(_) => {
-                        if ip_addr.take().is_some() {
-                            info!("Lost IP!");
-                            Self::stop(&mut daemon, &mut receiver);
+                        ServiceEvent::ServiceRemoved(_, fullname) => {
+                            debug!("mDNS service removed: {fullname}");
+                            if tx
+                                .send(super::Command::Mdns(Event::Lost(fullname)))
+                                .is_err()
+                            {
+                                break;
+                            }
                         }
+                        ServiceEvent::SearchStopped(_) => break,
+                        event => debug!("mDNS received event: {event:?}"),
                     }
                 }
-                ip_time = Instant::now();
-            }
-
-            if let Some(r) = &receiver {
-                match r.recv_timeout(Duration::from_millis(10)) {
-                    Ok(ServiceEvent::ServiceResolved(info)) => {
-                        if let Some(addr) = info.get_addresses_v4().iter().next() {
-                            debug!(
-                                "mDNS service resolved: {} {} {}:{}",
-                                info.get_fullname(),
-                                info.get_hostname(),
-                                addr,
-                                info.get_port(),
-                            );
-                            tx.send(SocketAddrV4::new(*addr, info.get_port())).unwrap();
-                        }
+                debug!("mDNS browse thread stopped");
+            },
+            move |msg| {
+                let _ = failed.send(super::Command::Mdns(Event::Failed(msg)));
+            },
+        )
+        .map_err(|e| format!("mDNS browse thread failed: {e}"))?;
+        mdns.handles.push(browse);
+        let monitor = worker::spawn(
+            "audio:mdns-monitor",
+            move || {
+                while let Ok(event) = monitor.recv() {
+                    match event {
+                        DaemonEvent::IpAdd(ip) => info!("New IP: {ip}"),
+                        DaemonEvent::IpDel(ip) => info!("Lost IP: {ip}"),
+                        event => debug!("mDNS daemon event: {event:?}"),
                     }
-                    Ok(event) => debug!("mDNS received event: {event:?}"),
-                    Err(_) => {}
                 }
-            }
-        }
+                debug!("mDNS monitor thread stopped");
+            },
+            |_| {},
+        )
+        .map_err(|e| format!("mDNS monitor thread failed: {e}"))?;
+        mdns.handles.push(monitor);
 
-        debug!("mDNS thread stopped");
+        Ok(mdns)
     }
 
-    fn start(
-        daemon: &mut Option<ServiceDaemon>,
-        receiver: &mut Option<MdnsReceiver<ServiceEvent>>,
-    ) {
-        if let (None, None) = (&daemon, &receiver) {
-            let d = ServiceDaemon::new().unwrap();
-            let r = d.browse(SERVICE_TYPE).unwrap();
-            *daemon = Some(d);
-            *receiver = Some(r);
-            debug!("mDNS daemon started");
-        }
-    }
-
-    fn stop(daemon: &mut Option<ServiceDaemon>, receiver: &mut Option<MdnsReceiver<ServiceEvent>>) {
-        if let (Some(d), Some(r)) = (&daemon, &receiver) {
-            d.stop_browse(SERVICE_TYPE).unwrap();
-            loop {
-                if let Ok(ServiceEvent::SearchStopped(_)) = r.recv() {
-                    break;
+    fn device(info: &ResolvedService) -> DiscoveredDevice {
+        let port = info.get_port();
+        let mut addresses: Vec<SocketAddr> = info
+            .get_addresses()
+            .iter()
+            .map(|ip| match ip {
+                ScopedIp::V4(v4) => SocketAddrV4::new(*v4.addr(), port).into(),
+                ScopedIp::V6(v6) => {
+                    let scope_id = if v6.addr().is_unicast_link_local() {
+                        v6.scope_id().index
+                    } else {
+                        0
+                    };
+                    SocketAddrV6::new(*v6.addr(), port, 0, scope_id).into()
                 }
-            }
-            d.shutdown().unwrap();
-            *receiver = None;
-            *daemon = None;
-            debug!("mDNS daemon stopped");
+                _ => SocketAddr::new(ip.to_ip_addr(), port),
+            })
+            .collect();
+        addresses.sort();
+        let mut interfaces: Vec<String> = info
+            .get_addresses()
+            .iter()
+            .flat_map(|ip| match ip {
+                ScopedIp::V4(v4) => v4.interface_ids().iter().map(|i| i.name.clone()).collect(),
+                ScopedIp::V6(v6) => vec![v6.scope_id().name.clone()],
+                _ => Vec::new(),
+            })
+            .filter(|name| !name.is_empty())
+            .collect();
+        interfaces.sort();
+        interfaces.dedup();
+        let device = DiscoveredDevice {
+            fullname: info.get_fullname().to_owned(),
+            hostname: info.get_hostname().to_owned(),
+            addresses,
+            interfaces,
+            name: info.get_property_val_str(TXT_NAME).map(str::to_owned),
+            firmware_version: info
+                .get_property_val_str(TXT_FIRMWARE_VERSION)
+                .map(str::to_owned),
+            protocol_version: info
+                .get_property_val_str(TXT_PROTOCOL_VERSION)
+                .and_then(|v| v.parse().ok()),
+            capabilities: info
+                .get_property_val_str(TXT_CAPABILITIES)
+                .map(|c| {
+                    c.split(',')
+                        .map(str::trim)
+                        .filter(|c| !c.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            properties: info.get_properties().clone().into_property_map_str(),
+        };
+        if !device.is_compatible() {
+            warn!(
+                "{} uses incompatible protocol version {:?}",
+                device.fullname, device.protocol_version
+            );
         }
+        device
     }
 }
 
 impl Drop for Mdns {
     fn drop(&mut self) {
-        self.sender.send(Command::Quit).unwrap();
-        self.handle.take().unwrap().join().unwrap();
+        // both fail only if the daemon is already gone
+        let _ = self.daemon.stop_browse(&self.service_type);
+        let _ = self.daemon.shutdown();
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+        debug!("mDNS daemon stopped");
     }
 }
@@ -1,106 +1,382 @@
 mod mdns;
 mod websocket;
 
+use std::net::SocketAddr;
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
-use std::thread::{Builder, JoinHandle};
-use std::time::Duration;
+use std::thread::JoinHandle;
+use std::time::Instant;
 
 use log::{debug, error, info};
 
 use self::mdns::Mdns;
 use self::websocket::WebSocket;
+use crate::common::worker;
+use crate::{BackendConfig, ConnectionState, DiscoveredDevice, Discovery};
 
 pub(crate) struct Com {
     handle: Option<JoinHandle<()>>,
     sender: Sender<Command>,
-    receiver: Receiver<Event>,
 }
 
 pub(crate) enum Event {
-    Connected,
+    // the device connected to, `fullname` is `None` for static endpoints and `connect_to`
+    Device {
+        fullname: Option<String>,
+        addr: SocketAddr,
+    },
+    // the address of the websocket that connected
+    Connected(SocketAddr),
     Disconnected,
     Message(String),
+    DeviceFound(DiscoveredDevice),
+    DeviceLost(String),
+    State(ConnectionState),
+    // a worker of com failed, com itself carries on
+    Failed(String),
+    // com itself failed and has stopped
+    Fatal(String),
 }
 
+struct Target {
+    addrs: Vec<SocketAddr>,
+    fullname: Option<String>,
+}
+
+// user commands as well as the events of mdns and the websocket arrive on the same channel
 enum Command {
     Quit,
+    Connect(SocketAddr),
+    Select(String),
     Message(String),
+    Mdns(mdns::Event),
+    WebSocket(u32, Event),
 }
 
 impl Com {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new<T: From<Event> + Send + 'static>(
+        config: BackendConfig,
+        tx: Sender<T>,
+    ) -> Self {
         let (sender, rx) = mpsc::channel();
-        let (tx, receiver) = mpsc::channel();
-        Self {
-            handle: Some(
-                Builder::new()
-                    .name("audio:com".into())
-                    .spawn(move || Self::thread(tx, rx))
-                    .unwrap(),
-            ),
-            sender,
-            receiver,
-        }
+        let own = sender.clone();
+        let failed = tx.clone();
+        let report = tx.clone();
+        let handle = worker::spawn(
+            "audio:com",
+            move || Self::thread(&tx, &own, rx, config),
+            move |msg| {
+                let _ = failed.send(Event::Fatal(msg).into());
+            },
+        );
+        let handle = match handle {
+            Ok(handle) => Some(handle),
+            Err(e) => {
+                let msg = format!("com thread failed: {e}");
+                error!("{msg}");
+                let _ = report.send(Event::Fatal(msg).into());
+                None
+            }
+        };
+        Self { handle, sender }
+    }
+
+    // the commands are lost if com has stopped, which it reports with Event::Fatal
+
+    pub(crate) fn connect(&self, addr: SocketAddr) {
+        let _ = self.sender.send(Command::Connect(addr));
     }
 
-    pub(crate) fn recv_timeout(&self, dur: Duration) -> Result<Event, RecvTimeoutError> {
-        self.receiver.recv_timeout(dur)
+    pub(crate) fn select(&self, fullname: String) {
+        let _ = self.sender.send(Command::Select(fullname));
     }
 
     pub(crate) fn send(&self, msg: String) {
-        self.sender.send(Command::Message(msg)).unwrap();
+        let _ = self.sender.send(Command::Message(msg));
     }
 
-    fn thread(tx: Sender<Event>, rx: Receiver<Command>) {
-        let mut mdns = Some(Mdns::new());
+    fn thread<T: From<Event>>(
+        tx: &Sender<T>,
+        own: &Sender<Command>,
+        rx: Receiver<Command>,
+        config: BackendConfig,
+    ) {
+        let mut endpoints = match &config.discovery {
+            Discovery::Mdns => Vec::new(),
+            Discovery::Static(endpoints) => endpoints.clone(),
+        };
+        let mut next_endpoint = 0;
+        let mut devices: Vec<DiscoveredDevice> = Vec::new();
+        let mut selected: Option<String> = None;
+        let mut last: Option<Target> = None;
+        let mut attempt = 0;
+        let mut retry_time: Option<Instant> = None;
         let mut websocket: Option<WebSocket> = None;
+        // events of a websocket that has already been dropped are ignored
+        let mut generation = 0;
+        let mut connected = false;
+        let mut state = ConnectionState::Discovering;
+
+        let mut mdns = None;
+        // mDNS is started on the first pass, and again after a failure if restarts are enabled
+        let mut mdns_start = endpoints.is_empty().then(Instant::now);
 
+        // the backend drops com before it stops, so sending to it only fails while shutting down
         loop {
-            // TODO: try to give the receiver to mdns and websocket so that they can directly send their messages and com doesn't need to poll
-            if let Ok(cmd) = rx.recv_timeout(Duration::from_millis(10)) {
-                match cmd {
-                    Command::Quit => {
-                        debug!("com thread received Quit");
-                        break;
-                    }
-                    Command::Message(msg) => {
-                        if let Some(ws) = &websocket {
-                            debug!("try to send: {msg}");
-                            ws.send(msg);
-                        } else {
-                            error!("not connected!");
-                        }
+            if mdns_start.is_some_and(|t| Instant::now() >= t) {
+                mdns_start = None;
+                match Mdns::new(&config.service_type, own.clone()) {
+                    Ok(m) => mdns = Some(m),
+                    Err(e) => {
+                        error!("{e}");
+                        worker::failed(e, &mut mdns_start, config.restart_delay, |msg| {
+                            let _ = tx.send(Event::Failed(msg).into());
+                        });
                     }
                 }
             }
 
-            if let Some(m) = &mdns
-                && let Ok(sock) = m.recv_timeout(Duration::from_millis(10))
+            if websocket.is_none()
+                && state != ConnectionState::Failed
+                && retry_time.is_none_or(|t| Instant::now() >= t)
             {
-                info!("mDNS result: {sock}");
-                mdns.take().unwrap();
-                websocket = Some(WebSocket::new(sock));
+                retry_time = None;
+                if !endpoints.is_empty() {
+                    let addr = endpoints[next_endpoint % endpoints.len()];
+                    next_endpoint += 1;
+                    last = Some(Target {
+                        addrs: vec![addr],
+                        fullname: None,
+                    });
+                } else if let Some(t) = &mut last {
+                    // retry the last known address, updated if the device is still announced
+                    if let Some(d) = devices
+                        .iter()
+                        .find(|d| Some(&d.fullname) == t.fullname.as_ref())
+                    {
+                        t.addrs.clone_from(&d.addresses);
+                    }
+                } else {
+                    last = selected
+                        .as_ref()
+                        .map_or_else(
+                            || devices.iter().find(|d| d.is_compatible()),
+                            |s| devices.iter().find(|d| &d.fullname == s),
+                        )
+                        .map(|d| Target {
+                            addrs: d.addresses.clone(),
+                            fullname: Some(d.fullname.clone()),
+                        });
+                }
+                if let Some(t) = &last {
+                    generation += 1;
+                    match WebSocket::new(t.addrs.clone(), config.clone(), generation, own.clone()) {
+                        Ok(ws) => {
+                            websocket = Some(ws);
+                            Self::set_state(&mut state, ConnectionState::Connecting, tx);
+                        }
+                        Err(e) => {
+                            let msg = format!("websocket failed: {e}");
+                            error!("{msg}");
+                            worker::failed(msg, &mut retry_time, config.restart_delay, |msg| {
+                                let _ = tx.send(Event::Failed(msg).into());
+                            });
+                            if retry_time.is_none() {
+                                Self::set_state(&mut state, ConnectionState::Failed, tx);
+                            }
+                        }
+                    }
+                } else {
+                    Self::set_state(&mut state, ConnectionState::Discovering, tx);
+                }
             }
 
-            if let Some(ws) = &websocket
-                && let Ok(evt) = ws.recv_timeout(Duration::from_millis(10))
-            {
-                if let Event::Disconnected = &evt {
-                    websocket.take().unwrap();
-                    mdns = Some(Mdns::new());
+            let cmd = match retry_time.into_iter().chain(mdns_start).min() {
+                Some(t) => match rx.recv_timeout(t.saturating_duration_since(Instant::now())) {
+                    Ok(cmd) => cmd,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                },
+                None => match rx.recv() {
+                    Ok(cmd) => cmd,
+                    Err(_) => break,
+                },
+            };
+
+            match cmd {
+                Command::Quit => {
+                    debug!("com thread received Quit");
+                    break;
+                }
+                Command::Connect(addr) => {
+                    info!("connect to {addr}");
+                    // an explicit address bypasses mDNS, which might not even work on the network
+                    mdns = None;
+                    mdns_start = None;
+                    for d in devices.drain(..) {
+                        let _ = tx.send(Event::DeviceLost(d.fullname).into());
+                    }
+                    endpoints = vec![addr];
+                    next_endpoint = 0;
+                    last = None;
+                    attempt = 0;
+                    retry_time = None;
+                    Self::disconnect(&mut websocket, &mut connected, tx);
+                    if state == ConnectionState::Failed {
+                        Self::set_state(&mut state, ConnectionState::Connecting, tx);
+                    }
+                }
+                Command::Select(fullname) => {
+                    info!("select device {fullname}");
+                    endpoints.clear();
+                    attempt = 0;
+                    retry_time = None;
+                    if last.as_ref().and_then(|t| t.fullname.as_ref()) != Some(&fullname) {
+                        last = None;
+                        Self::disconnect(&mut websocket, &mut connected, tx);
+                    }
+                    selected = Some(fullname);
+                    if state == ConnectionState::Failed {
+                        Self::set_state(&mut state, ConnectionState::Discovering, tx);
+                    }
+                }
+                Command::Message(msg) => {
+                    if let Some(ws) = &websocket {
+                        debug!("try to send: {msg}");
+                        ws.send(msg);
+                    } else {
+                        error!("not connected!");
+                    }
+                }
+                // sent before connect_to stopped mDNS
+                Command::Mdns(_) if mdns.is_none() => {}
+                Command::Mdns(mdns::Event::Found(device)) => {
+                    if let Some(d) = devices.iter_mut().find(|d| d.fullname == device.fullname) {
+                        if *d != device {
+                            *d = device.clone();
+                            let _ = tx.send(Event::DeviceFound(device).into());
+                        }
+                    } else {
+                        info!(
+                            "mDNS found device: {} {:?}",
+                            device.fullname, device.addresses
+                        );
+                        devices.push(device.clone());
+                        let _ = tx.send(Event::DeviceFound(device).into());
+                    }
+                }
+                Command::Mdns(mdns::Event::Failed(e)) => {
+                    mdns.take();
+                    worker::failed(e, &mut mdns_start, config.restart_delay, |msg| {
+                        let _ = tx.send(Event::Failed(msg).into());
+                    });
+                }
+                Command::Mdns(mdns::Event::Lost(fullname)) => {
+                    if let Some(i) = devices.iter().position(|d| d.fullname == fullname) {
+                        info!("mDNS lost device: {fullname}");
+                        devices.remove(i);
+                        // a device that is gone is not retried, the next pass browses again
+                        if websocket.is_none()
+                            && last.as_ref().and_then(|t| t.fullname.as_ref()) == Some(&fullname)
+                        {
+                            last = None;
+                            attempt = 0;
+                            retry_time = None;
+                        }
+                        let _ = tx.send(Event::DeviceLost(fullname).into());
+                    }
+                }
+                Command::WebSocket(id, evt) => {
+                    if id != generation || websocket.is_none() {
+                        continue;
+                    }
+                    match &evt {
+                        Event::Connected(addr) => {
+                            connected = true;
+                            attempt = 0;
+                            let fullname = last.as_ref().and_then(|t| t.fullname.clone());
+                            let device = Event::Device {
+                                fullname,
+                                addr: *addr,
+                            };
+                            let _ = tx.send(device.into());
+                            Self::set_state(&mut state, ConnectionState::Connected, tx);
+                        }
+                        Event::Disconnected => {
+                            connected = false;
+                            websocket.take().unwrap();
+                            attempt += 1;
+                            // the device was lost while connected
+                            let lost = last
+                                .as_ref()
+                                .and_then(|t| t.fullname.as_ref())
+                                .is_some_and(|f| devices.iter().all(|d| &d.fullname != f));
+                            if lost
+                                || config
+                                    .reconnect_max_attempts
+                                    .is_some_and(|max| attempt > max)
+                            {
+                                attempt = 0;
+                                if endpoints.is_empty() {
+                                    info!("giving up on last address, browsing again");
+                                    last = None;
+                                    Self::set_state(&mut state, ConnectionState::Discovering, tx);
+                                } else {
+                                    info!("giving up to connect");
+                                    Self::set_state(&mut state, ConnectionState::Failed, tx);
+                                }
+                            } else {
+                                let next_in = config
+                                    .reconnect_initial_delay
+                                    .saturating_mul(2_u32.saturating_pow(attempt - 1))
+                                    .min(config.reconnect_max_delay);
+                                retry_time = Some(Instant::now() + next_in);
+                                Self::set_state(
+                                    &mut state,
+                                    ConnectionState::Reconnecting { attempt, next_in },
+                                    tx,
+                                );
+                            }
+                        }
+                        _ => {}
+                    }
+                    let _ = tx.send(evt.into());
                 }
-                tx.send(evt).unwrap();
             }
         }
 
         debug!("exit com thread");
     }
+
+    fn set_state<T: From<Event>>(
+        state: &mut ConnectionState,
+        new: ConnectionState,
+        tx: &Sender<T>,
+    ) {
+        if *state != new {
+            debug!("connection state: {new:?}");
+            *state = new.clone();
+            let _ = tx.send(Event::State(new).into());
+        }
+    }
+
+    fn disconnect<T: From<Event>>(
+        websocket: &mut Option<WebSocket>,
+        connected: &mut bool,
+        tx: &Sender<T>,
+    ) {
+        if websocket.take().is_some() && *connected {
+            *connected = false;
+            let _ = tx.send(Event::Disconnected.into());
+        }
+    }
 }
 
 impl Drop for Com {
     fn drop(&mut self) {
-        self.sender.send(Command::Quit).unwrap();
-        self.handle.take().unwrap().join().unwrap();
+        let _ = self.sender.send(Command::Quit);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
     }
 }
@@ -1,110 +1,170 @@
-use std::io::ErrorKind::WouldBlock;
-use std::net::SocketAddrV4;
+use std::collections::VecDeque;
+use std::io;
+use std::io::ErrorKind::{Interrupted, WouldBlock};
+use std::net::{SocketAddr, TcpStream};
+use std::os::fd::AsRawFd;
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
 use std::thread::{Builder, JoinHandle};
 use std::time::{Duration, Instant};
 
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Token, Waker};
 use tungstenite::Bytes;
-use tungstenite::client::connect;
+use tungstenite::client::client;
 use tungstenite::error::Error::{ConnectionClosed, Io, Protocol};
 use tungstenite::error::ProtocolError::ResetWithoutClosingHandshake;
 use tungstenite::protocol::Message::{Ping, Pong, Text};
-use tungstenite::stream::MaybeTlsStream;
 
 use log::{debug, error};
 
 use super::Event;
+use crate::BackendConfig;
+use crate::common::worker;
+
+const ATTEMPT_DELAY_MS: u64 = 250;
+const SOCKET: Token = Token(0);
+const WAKER: Token = Token(1);
 
 pub(crate) struct WebSocket {
     handle: Option<JoinHandle<()>>,
     sender: Sender<Command>,
-    receiver: Receiver<Event>,
+    waker: Waker,
 }
 
 enum Command {
     Quit,
     Message(String),
+    // the result of a connection attempt
+    Attempt(SocketAddr, io::Result<TcpStream>),
 }
 
 impl WebSocket {
-    pub(crate) fn new(sock: SocketAddrV4) -> Self {
+    pub(crate) fn new(
+        addrs: Vec<SocketAddr>,
+        config: BackendConfig,
+        id: u32,
+        tx: Sender<super::Command>,
+    ) -> io::Result<Self> {
         let (sender, rx) = mpsc::channel();
-        let (tx, receiver) = mpsc::channel();
-        Self {
-            handle: Some(
-                Builder::new()
-                    .name("audio:websocket".into())
-                    .spawn(move || Self::thread(sock, tx, rx))
-                    .unwrap(),
-            ),
+        let poll = Poll::new()?;
+        let waker = Waker::new(poll.registry(), WAKER)?;
+        let failed = tx.clone();
+        let own = sender.clone();
+        let handle = worker::spawn(
+            "audio:websocket",
+            move || Self::thread(&addrs, id, &tx, &own, &rx, poll, &config),
+            // com reconnects as for any other lost connection
+            move |msg| {
+                let _ = failed.send(super::Command::WebSocket(id, Event::Failed(msg)));
+                let _ = failed.send(super::Command::WebSocket(id, Event::Disconnected));
+            },
+        )?;
+        Ok(Self {
+            handle: Some(handle),
             sender,
-            receiver,
-        }
-    }
-
-    pub(crate) fn recv_timeout(&self, dur: Duration) -> Result<Event, RecvTimeoutError> {
-        self.receiver.recv_timeout(dur)
+            waker,
+        })
     }
 
     pub(crate) fn send(&self, msg: String) {
-        self.sender.send(Command::Message(msg)).unwrap();
+        // the thread only stops after this websocket is dropped
+        let _ = self.sender.send(Command::Message(msg));
+        if let Err(e) = self.waker.wake() {
+            error!("ws wake error: {e:?}");
+        }
     }
 
-    fn thread(sock: SocketAddrV4, tx: Sender<Event>, rx: Receiver<Command>) {
-        let url = format!("ws://{}:{}/websocket", sock.ip(), sock.port());
+    fn thread(
+        addrs: &[SocketAddr],
+        id: u32,
+        tx: &Sender<super::Command>,
+        own: &Sender<Command>,
+        rx: &Receiver<Command>,
+        mut poll: Poll,
+        config: &BackendConfig,
+    ) {
+        // com only stops after dropping this websocket
+        let send = |evt| {
+            let _ = tx.send(super::Command::WebSocket(id, evt));
+        };
         let mut websocket = None;
+        // messages sent while connecting
+        let mut queued = Vec::new();
 
-        debug!("try to connect");
-        match connect(&url) {
-            Ok((ws, _)) => {
-                debug!("connected :)");
-                if let MaybeTlsStream::Plain(s) = ws.get_ref() {
-                    s.set_nonblocking(true).unwrap();
+        debug!("try to connect to {addrs:?}");
+        let connection = Self::connect(addrs, config.connect_timeout, own, rx, &mut queued);
+        let mut quit = connection.is_err();
+        if let Ok(Some((addr, stream))) = connection {
+            // the scope id is only needed for the TCP connection, not for the URL
+            let url = match addr {
+                SocketAddr::V4(a) => format!("ws://{a}{}", config.websocket_path),
+                SocketAddr::V6(a) => {
+                    format!("ws://[{}]:{}{}", a.ip(), a.port(), config.websocket_path)
                 }
-                websocket = Some(ws);
-                tx.send(Event::Connected).unwrap();
+            };
+            // a peer that accepts but never answers the upgrade must not block a Quit for long
+            if let Err(e) = stream
+                .set_read_timeout(Some(config.connect_timeout))
+                .and_then(|()| stream.set_write_timeout(Some(config.connect_timeout)))
+            {
+                error!("Error setting ws timeouts: {e:?}");
             }
-            Err(e) => {
-                error!("Error connecting ws: {e:?}");
+            match client(url, stream) {
+                Ok((ws, _)) => {
+                    let socket = ws.get_ref();
+                    match socket.set_nonblocking(true).and_then(|()| {
+                        poll.registry().register(
+                            &mut SourceFd(&socket.as_raw_fd()),
+                            SOCKET,
+                            Interest::READABLE | Interest::WRITABLE,
+                        )
+                    }) {
+                        Ok(()) => {
+                            debug!("connected :)");
+                            websocket = Some(ws);
+                            send(Event::Connected(addr));
+                        }
+                        Err(e) => error!("Error polling ws: {e:?}"),
+                    }
+                }
+                Err(e) => {
+                    error!("Error connecting ws: {e:?}");
+                }
             }
         }
 
         if let Some(mut ws) = websocket {
-            const PING_INTERVAL: u64 = 2;
-            const PONG_TIMEOUT: u64 = 5;
-            const CLOSE_TIMEOUT: u64 = 5;
-
+            let mut events = Events::with_capacity(8);
             let mut ping_time = Instant::now();
             let mut pong_time = Instant::now();
             let mut close_time = None;
+            for msg in queued {
+                Self::write(&mut ws, msg);
+            }
 
-            loop {
-                if let Ok(cmd) = rx.recv_timeout(Duration::from_millis(10)) {
+            'run: loop {
+                while let Ok(cmd) = rx.try_recv() {
                     match cmd {
                         Command::Quit => {
                             debug!("ws thread received Quit");
-                            ws.close(None).unwrap();
+                            quit = true;
+                            Self::close(&mut ws);
                             close_time = Some(Instant::now());
                         }
-                        Command::Message(msg) => {
-                            debug!("try to send message {msg}");
-                            match ws.send(Text(msg.into())) {
-                                Ok(()) => (),
-                                Err(e) => {
-                                    error!("ws send error: {e:?}");
-                                }
-                            }
-                        }
+                        Command::Message(msg) => Self::write(&mut ws, msg),
+                        // a slower attempt, the connection is already established
+                        Command::Attempt(..) => {}
                     }
                 }
 
-                if ping_time.elapsed() >= Duration::from_secs(PING_INTERVAL) {
+                if ping_time.elapsed() >= config.ping_interval {
                     if ws.can_write() {
                         match ws.send(Ping(Bytes::new())) {
                             Ok(()) => {
                                 debug!("ping...");
                             }
+                            Err(Io(e)) if e.kind() == WouldBlock => (),
                             Err(e) => {
                                 error!("ws send error: {e:?}");
                             }
@@ -116,64 +176,191 @@ impl WebSocket {
                 }
 
                 if let Some(t) = close_time {
-                    if t.elapsed() >= Duration::from_secs(CLOSE_TIMEOUT) {
-                        debug!("close not successful after {CLOSE_TIMEOUT}s => hard close");
+                    if t.elapsed() >= config.close_timeout {
+                        debug!(
+                            "close not successful after {:?} => hard close",
+                            config.close_timeout
+                        );
                         break;
                     }
-                } else if pong_time.elapsed() >= Duration::from_secs(PONG_TIMEOUT) {
-                    debug!("no pong received within {PONG_TIMEOUT}s");
-                    ws.close(None).unwrap();
+                } else if pong_time.elapsed() >= config.pong_timeout {
+                    debug!("no pong received within {:?}", config.pong_timeout);
+                    Self::close(&mut ws);
                     close_time = Some(Instant::now());
                 }
 
-                match ws.read() {
-                    Ok(Pong(_)) => {
-                        debug!("...pong");
-                        pong_time = Instant::now();
-                    }
-                    Ok(Text(s)) => {
-                        debug!("ws received message: {s}");
-                        tx.send(Event::Message(s.as_str().into())).unwrap();
-                    }
-                    Ok(msg) => {
-                        debug!("ws received {msg:?}");
-                    }
-                    Err(ConnectionClosed) => {
-                        debug!("ws connection closed with handshake");
-                        break;
-                    }
-                    Err(Protocol(ResetWithoutClosingHandshake)) => {
-                        debug!("ws connection closed without handshake");
-                        break;
-                    }
-                    Err(Io(e)) => {
-                        if e.kind() == WouldBlock {
-                            //debug!("would block...");
-                        } else {
+                match ws.flush() {
+                    Ok(()) => (),
+                    Err(Io(e)) if e.kind() == WouldBlock => (),
+                    Err(e) => debug!("ws flush error: {e:?}"),
+                }
+
+                // the socket is registered edge-triggered, so read until it would block
+                loop {
+                    match ws.read() {
+                        Ok(Pong(_)) => {
+                            debug!("...pong");
+                            pong_time = Instant::now();
+                        }
+                        Ok(Text(s)) => {
+                            debug!("ws received message: {s}");
+                            send(Event::Message(s.as_str().into()));
+                        }
+                        Ok(msg) => {
+                            debug!("ws received {msg:?}");
+                        }
+                        Err(ConnectionClosed) => {
+                            debug!("ws connection closed with handshake");
+                            break 'run;
+                        }
+                        Err(Protocol(ResetWithoutClosingHandshake)) => {
+                            debug!("ws connection closed without handshake");
+                            break 'run;
+                        }
+                        Err(Io(e)) => {
+                            if e.kind() == WouldBlock {
+                                break;
+                            }
                             error!("ws received IO error: {e:?}");
+                            break 'run;
+                        }
+                        Err(e) => {
+                            error!("ws received error: {e:?}");
                             break;
                         }
                     }
-                    Err(e) => {
-                        error!("ws received error: {e:?}");
-                    }
+                }
+
+                let deadline = close_time.map_or(pong_time + config.pong_timeout, |t| {
+                    t + config.close_timeout
+                });
+                let deadline = deadline.min(ping_time + config.ping_interval);
+                if let Err(e) = poll.poll(
+                    &mut events,
+                    Some(deadline.saturating_duration_since(Instant::now())),
+                ) && e.kind() != Interrupted
+                {
+                    error!("ws poll error: {e:?}");
+                    break;
                 }
             }
         }
 
-        tx.send(Event::Disconnected).unwrap();
+        send(Event::Disconnected);
 
-        if let Ok(Command::Quit) = rx.recv_timeout(Duration::from_secs(1)) {
-            debug!("ws thread received Quit after close");
+        // wait until com has dropped this websocket
+        while !quit {
+            match rx.recv() {
+                Ok(Command::Quit) => {
+                    debug!("ws thread received Quit after close");
+                    quit = true;
+                }
+                Ok(Command::Message(_) | Command::Attempt(..)) => {}
+                Err(_) => break,
+            }
         }
 
         debug!("ws thread stopped");
     }
+
+    fn write(ws: &mut tungstenite::WebSocket<TcpStream>, msg: String) {
+        debug!("try to send message {msg}");
+        match ws.send(Text(msg.into())) {
+            Ok(()) => (),
+            // the message stays queued and is flushed when the socket is writable
+            Err(Io(e)) if e.kind() == WouldBlock => (),
+            Err(e) => {
+                error!("ws send error: {e:?}");
+            }
+        }
+    }
+
+    fn close(ws: &mut tungstenite::WebSocket<TcpStream>) {
+        match ws.close(None) {
+            Ok(()) => (),
+            Err(Io(e)) if e.kind() == WouldBlock => (),
+            Err(e) => debug!("ws close error: {e:?}"),
+        }
+    }
+}
+
+impl WebSocket {
+    // returns Err if Quit was received while connecting, messages are added to `queued`
+    fn connect(
+        addrs: &[SocketAddr],
+        timeout: Duration,
+        own: &Sender<Command>,
+        rx: &Receiver<Command>,
+        queued: &mut Vec<String>,
+    ) -> Result<Option<(SocketAddr, TcpStream)>, ()> {
+        // Happy Eyeballs (RFC 8305): alternate address families starting with IPv6 and start
+        // a new attempt whenever the previous one did not succeed within the attempt delay.
+        let (v6, v4): (Vec<SocketAddr>, Vec<SocketAddr>) = addrs.iter().partition(|a| a.is_ipv6());
+        let mut order = VecDeque::new();
+        for i in 0..v6.len().max(v4.len()) {
+            order.extend(v6.get(i).copied());
+            order.extend(v4.get(i).copied());
+        }
+
+        // the attempts report to the command channel, so that a Quit is seen while they run
+        let mut running = 0;
+        let mut next_attempt = Instant::now();
+        loop {
+            if Instant::now() >= next_attempt
+                && let Some(addr) = order.pop_front()
+            {
+                let own = own.clone();
+                if let Err(e) = Builder::new().name("audio:connect".into()).spawn(move || {
+                    let res = TcpStream::connect_timeout(&addr, timeout);
+                    // a faster attempt might already have won
+                    let _ = own.send(Command::Attempt(addr, res));
+                }) {
+                    error!("Error connecting to {addr}: {e}");
+                    continue;
+                }
+                running += 1;
+                next_attempt = Instant::now() + Duration::from_millis(ATTEMPT_DELAY_MS);
+                continue;
+            }
+            if running == 0 && order.is_empty() {
+                return Ok(None);
+            }
+            let cmd = if order.is_empty() {
+                rx.recv().map_err(RecvTimeoutError::from)
+            } else {
+                rx.recv_timeout(next_attempt.saturating_duration_since(Instant::now()))
+            };
+            match cmd {
+                Ok(Command::Attempt(addr, Ok(stream))) => {
+                    debug!("connected to {addr}");
+                    return Ok(Some((addr, stream)));
+                }
+                Ok(Command::Attempt(addr, Err(e))) => {
+                    error!("Error connecting to {addr}: {e}");
+                    running -= 1;
+                    // the next address need not wait for the attempt delay
+                    next_attempt = Instant::now();
+                }
+                Ok(Command::Message(msg)) => queued.push(msg),
+                Ok(Command::Quit) => {
+                    debug!("ws thread received Quit while connecting");
+                    return Err(());
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(None),
+            }
+        }
+    }
 }
 
 impl Drop for WebSocket {
     fn drop(&mut self) {
-        self.sender.send(Command::Quit).unwrap();
-        self.handle.take().unwrap().join().unwrap();
+        let _ = self.sender.send(Command::Quit);
+        if let Err(e) = self.waker.wake() {
+            error!("ws wake error: {e:?}");
+        }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
     }
 }
@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
-use std::thread::{Builder, JoinHandle};
+use std::thread::JoinHandle;
 use std::time::{Duration, Instant};
 
 use dbus::arg::{self, PropMap, Variant};
@@ -22,16 +22,19 @@ use crate::common::dbus_codegen::networkmanager_device::{
 };
 use crate::common::dbus_codegen::networkmanager_settings::OrgFreedesktopNetworkManagerSettings;
 use crate::common::dbus_codegen::networkmanager_settings_connection::OrgFreedesktopNetworkManagerSettingsConnection;
+use crate::common::worker;
 
 const NM_DEVICE_TYPE_WIFI: u32 = 2;
-const SCAN_INTERVAL_SEC: u64 = 10;
-const PROXY_TIMEOUT_SEC: u64 = 5;
 
 pub(crate) struct Connector {
     handle: Option<JoinHandle<()>>,
     sender: Sender<Cmd>,
 }
 
+pub(crate) enum Event {
+    Failed(String),
+}
+
 enum Cmd {
     ActiveConnectionsChanged(Vec<Path<'static>>),
     ConnectionActive(bool),
@@ -40,51 +43,72 @@ enum Cmd {
 }
 
 impl Connector {
-    pub(crate) fn new(ssid: String, key: String) -> Self {
+    pub(crate) fn new<T: From<Event> + Send + 'static>(
+        ssid: String,
+        key: String,
+        scan_interval: Duration,
+        proxy_timeout: Duration,
+        failed: Sender<T>,
+    ) -> Result<Self, String> {
         let (sender, receiver) = mpsc::channel();
         let tx = sender.clone();
-        Self {
-            handle: Some(
-                Builder::new()
-                    .name("access_point".into())
-                    .spawn(move || Self::thread(tx, receiver, &ssid, &key))
-                    .unwrap(),
-            ),
+        let on_panic = failed.clone();
+        let handle = worker::spawn(
+            "access_point",
+            move || {
+                if let Err(e) =
+                    Self::thread(tx, receiver, &ssid, &key, scan_interval, proxy_timeout)
+                {
+                    let msg = format!("access point failed: {e}");
+                    error!("{msg}");
+                    let _ = failed.send(Event::Failed(msg).into());
+                }
+            },
+            move |msg| {
+                let _ = on_panic.send(Event::Failed(msg).into());
+            },
+        )
+        .map_err(|e| format!("access point thread failed: {e}"))?;
+        Ok(Self {
+            handle: Some(handle),
             sender,
-        }
+        })
     }
 
-    fn thread(tx: Sender<Cmd>, rx: Receiver<Cmd>, ssid: &str, key: &str) {
-        let conn = Connection::new_system().unwrap();
+    fn thread(
+        tx: Sender<Cmd>,
+        rx: Receiver<Cmd>,
+        ssid: &str,
+        key: &str,
+        scan_interval: Duration,
+        proxy_timeout: Duration,
+    ) -> Result<(), dbus::Error> {
+        let conn = Connection::new_system()?;
 
         let proxy = conn.with_proxy(
             "org.freedesktop.NetworkManager",
             "/org/freedesktop/NetworkManager",
-            Duration::from_secs(PROXY_TIMEOUT_SEC),
+            proxy_timeout,
         );
 
         let txc = tx.clone();
-        proxy
-            .match_signal(
-                move |p: OrgFreedesktopDBusPropertiesPropertiesChanged,
-                      _: &Connection,
-                      _: &Message| {
-                    if let Some(acs) =
-                        arg::prop_cast::<Vec<Path>>(&p.changed_properties, "ActiveConnections")
-                    {
-                        txc.send(Cmd::ActiveConnectionsChanged(acs.clone()))
-                            .unwrap();
-                    }
-                    true
-                },
-            )
-            .unwrap();
+        proxy.match_signal(
+            move |p: OrgFreedesktopDBusPropertiesPropertiesChanged, _: &Connection, _: &Message| {
+                if let Some(acs) =
+                    arg::prop_cast::<Vec<Path>>(&p.changed_properties, "ActiveConnections")
+                {
+                    // keep matching only while the thread is running
+                    return txc.send(Cmd::ActiveConnectionsChanged(acs.clone())).is_ok();
+                }
+                true
+            },
+        )?;
 
         let connection = {
             let proxy_settings = conn.with_proxy(
                 "org.freedesktop.NetworkManager",
                 "/org/freedesktop/NetworkManager/Settings",
-                Duration::from_secs(PROXY_TIMEOUT_SEC),
+                proxy_timeout,
             );
 
             let mut connection = PropMap::new();
@@ -114,7 +138,7 @@ impl Connector {
                     let proxy_connection = conn.with_proxy(
                         "org.freedesktop.NetworkManager",
                         path_connection.clone(),
-                        Duration::from_secs(PROXY_TIMEOUT_SEC),
+                        proxy_timeout,
                     );
                     Some((path_connection, proxy_connection))
                 }
@@ -132,43 +156,51 @@ impl Connector {
                     let proxy_device = conn.with_proxy(
                         "org.freedesktop.NetworkManager",
                         path_device.clone(),
-                        Duration::from_secs(PROXY_TIMEOUT_SEC),
+                        proxy_timeout,
                     );
 
-                    if proxy_device.device_type().unwrap() == NM_DEVICE_TYPE_WIFI {
+                    if proxy_device
+                        .device_type()
+                        .is_ok_and(|t| t == NM_DEVICE_TYPE_WIFI)
+                    {
                         let txc = tx.clone();
-                        proxy_device
-                            .match_signal(
-                                move |p: OrgFreedesktopDBusPropertiesPropertiesChanged,
-                                      _: &Connection,
-                                      _: &Message| {
-                                    if p.changed_properties.contains_key("LastScan") {
-                                        txc.send(Cmd::ScanFinished).unwrap();
-                                    }
-                                    true
-                                },
-                            )
-                            .unwrap();
-                        device = Some((path_device, proxy_device));
-                        break;
+                        match proxy_device.match_signal(
+                            move |p: OrgFreedesktopDBusPropertiesPropertiesChanged,
+                                  _: &Connection,
+                                  _: &Message| {
+                                if p.changed_properties.contains_key("LastScan") {
+                                    return txc.send(Cmd::ScanFinished).is_ok();
+                                }
+                                true
+                            },
+                        ) {
+                            Ok(_) => {
+                                device = Some((path_device, proxy_device));
+                                break;
+                            }
+                            Err(e) => error!("Could not watch WiFi device: {e}"),
+                        }
                     }
                 }
             }
             device
         };
 
+        let mut res = Ok(());
         if let (Some((path_connection, _)), Some((path_device, proxy_device))) =
             (&connection, &device)
         {
             let mut last_scan_time = Instant::now()
-                .checked_sub(Duration::from_secs(SCAN_INTERVAL_SEC))
+                .checked_sub(scan_interval)
                 .unwrap_or(Instant::now());
             let mut path_active_connection = None;
             let mut active = false;
 
             loop {
-                if !active && last_scan_time.elapsed() > Duration::from_secs(SCAN_INTERVAL_SEC) {
-                    proxy_device.request_scan(HashMap::new()).unwrap();
+                if !active && last_scan_time.elapsed() > scan_interval {
+                    if let Err(e) = proxy_device.request_scan(HashMap::new()) {
+                        error!("Could not request WiFi scan: {e}");
+                    }
                     last_scan_time = Instant::now();
                 }
 
@@ -180,7 +212,7 @@ impl Connector {
                                 let proxy_ac = conn.with_proxy(
                                     "org.freedesktop.NetworkManager",
                                     ac.clone(),
-                                    Duration::from_secs(PROXY_TIMEOUT_SEC),
+                                    proxy_timeout,
                                 );
                                 if let Ok(c) = &proxy_ac.connection()
                                     && c == path_connection
@@ -191,16 +223,15 @@ impl Connector {
                                     } else {
                                         path_active_connection = Some(ac);
                                         let txc = tx.clone();
-                                        proxy_ac
-                                            .match_signal(
-                                                move |s: OrgFreedesktopNetworkManagerConnectionActiveStateChanged,
-                                                      _: &Connection,
-                                                      _: &Message| {
-                                                          txc.send(Cmd::ConnectionActive(s.state <= 2)).unwrap();
-                                                    true
-                                                },
-                                            )
-                                            .unwrap();
+                                        if let Err(e) = proxy_ac.match_signal(
+                                            move |s: OrgFreedesktopNetworkManagerConnectionActiveStateChanged,
+                                                  _: &Connection,
+                                                  _: &Message| {
+                                                txc.send(Cmd::ConnectionActive(s.state <= 2)).is_ok()
+                                            },
+                                        ) {
+                                            error!("Could not watch AP connection: {e}");
+                                        }
                                     }
                                 }
                             }
@@ -219,9 +250,9 @@ impl Connector {
                                         let proxy_ap = conn.with_proxy(
                                             "org.freedesktop.NetworkManager",
                                             &ap,
-                                            Duration::from_secs(PROXY_TIMEOUT_SEC),
+                                            proxy_timeout,
                                         );
-                                        if proxy_ap.ssid().unwrap() == ssid.as_bytes()
+                                        if proxy_ap.ssid().is_ok_and(|s| s == ssid.as_bytes())
                                             && let Err(e) = proxy.activate_connection(
                                                 path_connection.to_owned(),
                                                 path_device.to_owned(),
@@ -236,7 +267,10 @@ impl Connector {
                         }
                     }
                 }
-                conn.process(Duration::from_millis(10)).unwrap();
+                if let Err(e) = conn.process(Duration::from_millis(10)) {
+                    res = Err(e);
+                    break;
+                }
             }
         }
 
@@ -245,12 +279,16 @@ impl Connector {
         {
             error!("Could not delete AP connection: {e}");
         }
+        res
     }
 }
 
 impl Drop for Connector {
     fn drop(&mut self) {
-        self.sender.send(Cmd::Quit).unwrap();
-        self.handle.take().unwrap().join().unwrap();
+        // a failed thread has already reported its error
+        let _ = self.sender.send(Cmd::Quit);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
     }
 }
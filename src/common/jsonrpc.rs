@@ -2,28 +2,39 @@ use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
+use std::time::{Duration, Instant};
 
 use log::error;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 const RPC_VERSION: &str = "2.0";
+const DEFAULT_TIMEOUT_SEC: u64 = 5;
 
-#[derive(Default)]
 pub(crate) struct Handler {
     id: Cell<u32>,
-    map: RefCell<HashMap<u32, &'static str>>,
+    map: RefCell<HashMap<u32, Pending>>,
+    timeout: Cell<Duration>,
 }
 
-pub(crate) enum Message<'a> {
+struct Pending {
+    method: &'static str,
+    deadline: Instant,
+}
+
+pub(crate) enum Message {
     Response {
-        method: &'a str,
+        id: u32,
         data: Result<Value, ExecError>,
     },
-    //Notification {
-    //    method: &'a str,
-    //    data: Value,
-    //},
+    Notification {
+        method: String,
+        data: Value,
+    },
+    Invalid {
+        id: Option<u32>,
+        error: String,
+    },
 }
 
 #[derive(Deserialize, Debug)]
@@ -44,60 +55,161 @@ struct Request<'a> {
 
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
-struct Response<'a> {
-    jsonrpc: &'a str,
+struct Response {
+    jsonrpc: String,
     result: Option<Value>,
     error: Option<ExecError>,
     id: Option<u32>,
+    method: Option<String>,
+    params: Option<Value>,
 }
 
 impl Handler {
-    pub(crate) fn build_request(&self, method: &'static str, params: Option<Value>) -> String {
+    pub(crate) fn build_request(
+        &self,
+        method: &'static str,
+        params: Option<Value>,
+        timeout: Option<Duration>,
+    ) -> (u32, String) {
+        let request = self.request(method, params, timeout);
+        (request.id, serde_json::to_string(&request).unwrap())
+    }
+
+    pub(crate) fn build_batch(
+        &self,
+        calls: Vec<(&'static str, Option<Value>)>,
+    ) -> (Vec<u32>, String) {
+        let requests: Vec<Request> = calls
+            .into_iter()
+            .map(|(method, params)| self.request(method, params, None))
+            .collect();
+        (
+            requests.iter().map(|r| r.id).collect(),
+            serde_json::to_string(&requests).unwrap(),
+        )
+    }
+
+    // without a timeout of its own the request gets the default one
+    fn request(
+        &self,
+        method: &'static str,
+        params: Option<Value>,
+        timeout: Option<Duration>,
+    ) -> Request<'static> {
         let id = self.id.get() + 1;
         self.id.set(id);
-        self.map.borrow_mut().insert(id, method);
-        let request = Request {
+        self.map.borrow_mut().insert(
+            id,
+            Pending {
+                method,
+                deadline: Instant::now() + timeout.unwrap_or(self.timeout.get()),
+            },
+        );
+        Request {
             jsonrpc: RPC_VERSION,
             method,
             params,
             id,
-        };
-        serde_json::to_string(&request).unwrap()
-    }
-
-    pub(crate) fn parse(&self, msg: &str) -> Option<Message<'_>> {
-        match serde_json::from_str::<Response>(msg) {
-            Ok(rpc) => {
-                if rpc.jsonrpc != RPC_VERSION {
-                    error!("Invalid jsonrpc version: {}", rpc.jsonrpc);
-                    return None;
-                }
-                if let Some(id) = rpc.id
-                    && let Some(method) = self.map.borrow_mut().remove(&id)
-                {
-                    if let Some(error) = rpc.error {
-                        return Some(Message::Response {
-                            method,
-                            data: Err(error),
-                        });
-                    } else if let Some(result) = rpc.result {
-                        return Some(Message::Response {
-                            method,
-                            data: Ok(result),
-                        });
-                    }
-                    error!("Received neither result nor error...");
-                    return None;
-                }
-                error!("Received unrelated message id...");
-                None
+        }
+    }
+
+    pub(crate) fn set_timeout(&self, timeout: Duration) {
+        self.timeout.set(timeout);
+    }
+
+    pub(crate) fn expire(&self) -> Vec<u32> {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        self.map.borrow_mut().retain(|id, pending| {
+            if pending.deadline > now {
+                return true;
             }
+            error!("Request {id} ({}) timed out", pending.method);
+            expired.push(*id);
+            false
+        });
+        expired
+    }
+
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.map.borrow().values().map(|p| p.deadline).min()
+    }
+
+    pub(crate) fn clear(&self) -> Vec<u32> {
+        self.map.borrow_mut().drain().map(|(id, _)| id).collect()
+    }
+
+    pub(crate) fn parse(&self, msg: &str) -> Vec<Message> {
+        match serde_json::from_str::<Value>(msg) {
+            // the responses to a batch request arrive as an array in any order
+            Ok(Value::Array(batch)) => batch.into_iter().map(|rpc| self.message(rpc)).collect(),
+            Ok(rpc) => vec![self.message(rpc)],
+            Err(e) => vec![Self::invalid(None, format!("Could not parse jsonrpc: {e}"))],
+        }
+    }
+
+    // a malformed response only fails the request of its own id
+    fn message(&self, rpc: Value) -> Message {
+        let id = rpc.get("id").and_then(Value::as_u64);
+        match serde_json::from_value::<Response>(rpc) {
+            Ok(rpc) => self.dispatch(rpc),
             Err(e) => {
-                error!("Could not parse jsonrpc: {e}");
-                None
+                let id = id
+                    .and_then(|id| u32::try_from(id).ok())
+                    .filter(|id| self.map.borrow_mut().remove(id).is_some());
+                Self::invalid(id, format!("Could not parse jsonrpc: {e}"))
             }
         }
     }
+
+    fn dispatch(&self, rpc: Response) -> Message {
+        // a pending request is resolved by any message carrying its id, even an invalid one
+        let id = rpc
+            .id
+            .filter(|id| self.map.borrow_mut().remove(id).is_some());
+        if rpc.jsonrpc != RPC_VERSION {
+            return Self::invalid(id, format!("Invalid jsonrpc version: {}", rpc.jsonrpc));
+        }
+        if rpc.id.is_none()
+            && let Some(method) = rpc.method
+        {
+            return Message::Notification {
+                method,
+                data: rpc.params.unwrap_or_default(),
+            };
+        }
+        let Some(id) = id else {
+            return Self::invalid(None, format!("Received unrelated message id {:?}", rpc.id));
+        };
+        if let Some(error) = rpc.error {
+            Message::Response {
+                id,
+                data: Err(error),
+            }
+        } else if let Some(result) = rpc.result {
+            Message::Response {
+                id,
+                data: Ok(result),
+            }
+        } else {
+            Self::invalid(Some(id), "Received neither result nor error".to_owned())
+        }
+    }
+
+    fn invalid(id: Option<u32>, error: String) -> Message {
+        error!("{error}");
+        Message::Invalid { id, error }
+    }
+}
+
+impl Default for Handler {
+    fn default() -> Self {
+        Self {
+            id: Cell::default(),
+            map: RefCell::default(),
+            timeout: Cell::new(Duration::from_secs(DEFAULT_TIMEOUT_SEC)),
+        }
+    }
 }
 
 impl Error for ExecError {}
@@ -107,3 +219,112 @@ impl fmt::Display for ExecError {
         write!(f, "{} ({})", self.message, self.code)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch(handler: &Handler) -> Vec<u32> {
+        let (ids, _) = handler.build_batch(vec![("a", None), ("b", None)]);
+        ids
+    }
+
+    #[test]
+    fn batch_responses_in_any_order() {
+        let handler = Handler::default();
+        let ids = batch(&handler);
+        let msg = format!(
+            r#"[{{"jsonrpc":"2.0","error":{{"code":-32601,"message":"no"}},"id":{}}},
+                {{"jsonrpc":"2.0","result":1,"id":{}}}]"#,
+            ids[1], ids[0]
+        );
+        let messages = handler.parse(&msg);
+        assert!(matches!(
+            &messages[..],
+            [
+                Message::Response { id: b, data: Err(ExecError { code: -32601, .. }) },
+                Message::Response { id: a, data: Ok(_) },
+            ] if *a == ids[0] && *b == ids[1]
+        ));
+        assert!(handler.clear().is_empty());
+    }
+
+    #[test]
+    fn notification() {
+        let handler = Handler::default();
+        let (id, _) = handler.build_request("a", None, None);
+        let msg = r#"{"jsonrpc":"2.0","method":"volume-changed","params":{"volume":3}}"#;
+        assert!(matches!(
+            &handler.parse(msg)[..],
+            [Message::Notification { method, data }]
+                if method == "volume-changed" && data["volume"] == 3
+        ));
+        // a notification does not resolve any request
+        assert_eq!(handler.clear(), [id]);
+    }
+
+    #[test]
+    fn unknown_id() {
+        let handler = Handler::default();
+        let (id, _) = handler.build_request("a", None, None);
+        let msg = format!(r#"{{"jsonrpc":"2.0","result":1,"id":{}}}"#, id + 1);
+        assert!(matches!(
+            &handler.parse(&msg)[..],
+            [Message::Invalid { id: None, .. }]
+        ));
+        // the answered request stays pending
+        assert_eq!(handler.clear(), [id]);
+    }
+
+    #[test]
+    fn invalid_response_resolves_its_id() {
+        let handler = Handler::default();
+        let (id, _) = handler.build_request("a", None, None);
+        let msg = format!(r#"{{"jsonrpc":"2.0","id":{id}}}"#);
+        assert!(matches!(
+            &handler.parse(&msg)[..],
+            [Message::Invalid { id: Some(i), .. }] if *i == id
+        ));
+        assert!(handler.clear().is_empty());
+    }
+
+    #[test]
+    fn invalid_element_only_fails_its_id() {
+        let handler = Handler::default();
+        let ids = batch(&handler);
+        let msg = format!(
+            r#"[{{"jsonrpc":"2.0","result":1,"id":{}}},{{"jsonrpc":"2.0","bogus":1,"id":{}}}]"#,
+            ids[0], ids[1]
+        );
+        assert!(matches!(
+            &handler.parse(&msg)[..],
+            [
+                Message::Response { id: a, data: Ok(_) },
+                Message::Invalid { id: Some(b), .. },
+            ] if *a == ids[0] && *b == ids[1]
+        ));
+        assert!(handler.clear().is_empty());
+
+        // without any known id the requests expire
+        let ids = batch(&handler);
+        assert!(matches!(
+            &handler.parse("[{")[..],
+            [Message::Invalid { id: None, .. }]
+        ));
+        assert_eq!(handler.clear().len(), ids.len());
+    }
+
+    #[test]
+    fn expires_after_timeout() {
+        let handler = Handler::default();
+        let (default, _) = handler.build_request("a", None, None);
+        let (own, _) = handler.build_request("b", None, Some(Duration::ZERO));
+        assert_eq!(handler.expire(), [own]);
+        handler.set_timeout(Duration::ZERO);
+        let (id, _) = handler.build_request("c", None, None);
+        assert_eq!(handler.expire(), [id]);
+        // the default applies when a request is sent
+        assert_eq!(handler.clear(), [default]);
+        assert!(handler.next_deadline().is_none());
+    }
+}
@@ -1,4 +1,6 @@
 pub(crate) mod access_point;
 pub(crate) mod jsonrpc;
+pub(crate) mod oneshot;
+pub(crate) mod worker;
 
 mod dbus_codegen;
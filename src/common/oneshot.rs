@@ -0,0 +1,142 @@
+use std::sync::mpsc::{RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::task::Waker;
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+// a channel for a single value that can be awaited as well as waited for
+
+pub(crate) struct Sender<T> {
+    slot: Arc<Slot<T>>,
+}
+
+pub(crate) struct Receiver<T> {
+    slot: Arc<Slot<T>>,
+}
+
+struct Slot<T> {
+    state: Mutex<State<T>>,
+    cond: Condvar,
+}
+
+struct State<T> {
+    value: Option<T>,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+pub(crate) fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let slot = Arc::new(Slot {
+        state: Mutex::new(State {
+            value: None,
+            closed: false,
+            waker: None,
+        }),
+        cond: Condvar::new(),
+    });
+    (Sender { slot: slot.clone() }, Receiver { slot })
+}
+
+impl<T> Sender<T> {
+    pub(crate) fn send(self, value: T) {
+        self.slot.lock().value = Some(value);
+        // dropping self wakes the receiver
+    }
+}
+
+impl<T> Receiver<T> {
+    pub(crate) fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.slot.lock();
+        match state.value.take() {
+            Some(value) => Ok(value),
+            None if state.closed => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    pub(crate) fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let state = self.slot.lock();
+        let (mut state, _) = self
+            .slot
+            .cond
+            .wait_timeout_while(state, timeout, |s| s.value.is_none() && !s.closed)
+            .unwrap_or_else(PoisonError::into_inner);
+        match state.value.take() {
+            Some(value) => Ok(value),
+            None if state.closed => Err(RecvTimeoutError::Disconnected),
+            None => Err(RecvTimeoutError::Timeout),
+        }
+    }
+
+    // like try_recv, but wakes the task of `cx` once a value arrives or the sender is dropped
+    #[cfg(feature = "async")]
+    pub(crate) fn poll_recv(&self, cx: &Context<'_>) -> Poll<Result<T, TryRecvError>> {
+        let mut state = self.slot.lock();
+        match state.value.take() {
+            Some(value) => Poll::Ready(Ok(value)),
+            None if state.closed => Poll::Ready(Err(TryRecvError::Disconnected)),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Slot<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state = self.slot.lock();
+            state.closed = true;
+            state.waker.take()
+        };
+        self.slot.cond.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn value_then_disconnected() {
+        let (sender, receiver) = channel();
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+        sender.send(1);
+        assert_eq!(receiver.try_recv(), Ok(1));
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn dropped_sender() {
+        let (sender, receiver) = channel::<u8>();
+        drop(sender);
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            receiver.recv_timeout(timeout),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn waits_for_value() {
+        let (sender, receiver) = channel();
+        let short = Duration::from_millis(10);
+        assert_eq!(receiver.recv_timeout(short), Err(RecvTimeoutError::Timeout));
+        let handle = thread::spawn(move || sender.send(2));
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(2));
+        handle.join().unwrap();
+    }
+}
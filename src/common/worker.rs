@@ -0,0 +1,55 @@
+use std::any::Any;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread::{Builder, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::error;
+
+/// Runs `f` and catches a panic in it, returning the panic message.
+pub(crate) fn run(name: &str, f: impl FnOnce()) -> Result<(), String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        let msg = format!("{name} thread panicked: {}", message(&*payload));
+        error!("{msg}");
+        msg
+    })
+}
+
+/// Spawns a named thread running `f`, a panic in it is passed to `on_panic` instead of
+/// surfacing in the `join` of the owner.
+pub(crate) fn spawn(
+    name: &str,
+    f: impl FnOnce() + Send + 'static,
+    on_panic: impl FnOnce(String) + Send + 'static,
+) -> io::Result<JoinHandle<()>> {
+    let thread = name.to_owned();
+    Builder::new().name(thread.clone()).spawn(move || {
+        if let Err(msg) = run(&thread, f) {
+            on_panic(msg);
+        }
+    })
+}
+
+/// Reports a failed worker with `report` and schedules its restart `delay` from now, or never if
+/// restarts are disabled.
+///
+/// A worker that panicked has already reported itself this way from its own thread (see
+/// [`spawn`]), so the owner ignores the result of `join` when the worker is dropped.
+pub(crate) fn failed(
+    msg: String,
+    restart: &mut Option<Instant>,
+    delay: Option<Duration>,
+    report: impl FnOnce(String),
+) {
+    *restart = delay.map(|d| Instant::now() + d);
+    report(msg);
+}
+
+/// The message of a caught panic.
+pub(crate) fn message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
@@ -0,0 +1,275 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::Error;
+
+const AP_SSID: &str = "esp32-audio";
+const AP_KEY: &str = "secret-wifi-key";
+const SERVICE_TYPE: &str = "_audio-jsonrpc-websocket._tcp.local.";
+const WEBSOCKET_PATH: &str = "/websocket";
+const CONNECT_TIMEOUT_SEC: u64 = 5;
+const PING_INTERVAL_SEC: u64 = 2;
+const PONG_TIMEOUT_SEC: u64 = 5;
+const CLOSE_TIMEOUT_SEC: u64 = 5;
+const SCAN_INTERVAL_SEC: u64 = 10;
+const PROXY_TIMEOUT_SEC: u64 = 5;
+const REQUEST_TIMEOUT_SEC: u64 = 5;
+const RECONNECT_INITIAL_DELAY_SEC: u64 = 1;
+const RECONNECT_MAX_DELAY_SEC: u64 = 60;
+
+/// Settings of a [`Backend`](crate::Backend).
+///
+/// Durations in a configuration file are given in seconds, keys are kebab-case, e.g.
+/// `{"service-type": "_audio-jsonrpc-websocket._tcp.local.", "ping-interval": 2}`.
+/// Missing keys keep their default value.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BackendConfig {
+    pub(crate) discovery: Discovery,
+    pub(crate) ap_ssid: String,
+    pub(crate) ap_key: String,
+    pub(crate) service_type: String,
+    pub(crate) websocket_path: String,
+    #[serde(deserialize_with = "secs")]
+    pub(crate) connect_timeout: Duration,
+    #[serde(deserialize_with = "secs")]
+    pub(crate) ping_interval: Duration,
+    #[serde(deserialize_with = "secs")]
+    pub(crate) pong_timeout: Duration,
+    #[serde(deserialize_with = "secs")]
+    pub(crate) close_timeout: Duration,
+    #[serde(deserialize_with = "secs")]
+    pub(crate) scan_interval: Duration,
+    #[serde(deserialize_with = "secs")]
+    pub(crate) proxy_timeout: Duration,
+    #[serde(deserialize_with = "secs")]
+    pub(crate) request_timeout: Duration,
+    #[serde(deserialize_with = "secs")]
+    pub(crate) reconnect_initial_delay: Duration,
+    #[serde(deserialize_with = "secs")]
+    pub(crate) reconnect_max_delay: Duration,
+    pub(crate) reconnect_max_attempts: Option<u32>,
+    #[serde(deserialize_with = "opt_secs")]
+    pub(crate) restart_delay: Option<Duration>,
+    pub(crate) cache_dir: Option<PathBuf>,
+}
+
+/// How the device to connect to is found.
+///
+/// In a configuration file this is either `"mdns"` or `{"static": ["192.168.4.1:80"]}`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Discovery {
+    #[default]
+    Mdns,
+    Static(Vec<SocketAddr>),
+}
+
+impl BackendConfig {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .map_err(|e| Error::Config(format!("{}: {e}", path.display())))?;
+        serde_json::from_str(&content)
+            .map_err(|e| Error::Config(format!("{}: {e}", path.display())))
+    }
+
+    #[must_use]
+    pub fn discovery(mut self, discovery: Discovery) -> Self {
+        self.discovery = discovery;
+        self
+    }
+
+    #[must_use]
+    pub fn access_point(mut self, ssid: String, key: String) -> Self {
+        self.ap_ssid = ssid;
+        self.ap_key = key;
+        self
+    }
+
+    #[must_use]
+    pub fn service_type(mut self, service_type: String) -> Self {
+        self.service_type = service_type;
+        self
+    }
+
+    #[must_use]
+    pub fn websocket_path(mut self, path: String) -> Self {
+        self.websocket_path = path;
+        self
+    }
+
+    #[must_use]
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    pub fn ping_interval(mut self, interval: Duration) -> Self {
+        self.ping_interval = interval;
+        self
+    }
+
+    #[must_use]
+    pub fn pong_timeout(mut self, timeout: Duration) -> Self {
+        self.pong_timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    pub fn close_timeout(mut self, timeout: Duration) -> Self {
+        self.close_timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    pub fn scan_interval(mut self, interval: Duration) -> Self {
+        self.scan_interval = interval;
+        self
+    }
+
+    #[must_use]
+    pub fn proxy_timeout(mut self, timeout: Duration) -> Self {
+        self.proxy_timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// The delay before a reconnect starts at `initial` and doubles with every failed attempt
+    /// up to `max`.
+    #[must_use]
+    pub fn reconnect_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.reconnect_initial_delay = initial;
+        self.reconnect_max_delay = max;
+        self
+    }
+
+    /// Number of reconnect attempts to the last address before giving up, unlimited if `None`.
+    ///
+    /// With mDNS discovery the devices are browsed again afterwards, with static endpoints the
+    /// connection state becomes [`Failed`](crate::ConnectionState::Failed).
+    #[must_use]
+    pub fn reconnect_max_attempts(mut self, attempts: Option<u32>) -> Self {
+        self.reconnect_max_attempts = attempts;
+        self
+    }
+
+    /// Restarts a failed worker, like the com thread or the mDNS daemon, after `delay`.
+    /// Failed workers are only reported with [`Event::Fatal`](crate::Event::Fatal) if `None`.
+    #[must_use]
+    pub fn restart_failed(mut self, delay: Option<Duration>) -> Self {
+        self.restart_delay = delay;
+        self
+    }
+
+    /// Directory of the track cache of each device, `$XDG_CACHE_HOME/audio-backend` by default.
+    #[must_use]
+    pub fn cache_dir(mut self, dir: PathBuf) -> Self {
+        self.cache_dir = Some(dir);
+        self
+    }
+}
+
+impl Default for BackendConfig {
+    fn default() -> Self {
+        Self {
+            discovery: Discovery::default(),
+            ap_ssid: AP_SSID.to_owned(),
+            ap_key: AP_KEY.to_owned(),
+            service_type: SERVICE_TYPE.to_owned(),
+            websocket_path: WEBSOCKET_PATH.to_owned(),
+            connect_timeout: Duration::from_secs(CONNECT_TIMEOUT_SEC),
+            ping_interval: Duration::from_secs(PING_INTERVAL_SEC),
+            pong_timeout: Duration::from_secs(PONG_TIMEOUT_SEC),
+            close_timeout: Duration::from_secs(CLOSE_TIMEOUT_SEC),
+            scan_interval: Duration::from_secs(SCAN_INTERVAL_SEC),
+            proxy_timeout: Duration::from_secs(PROXY_TIMEOUT_SEC),
+            request_timeout: Duration::from_secs(REQUEST_TIMEOUT_SEC),
+            reconnect_initial_delay: Duration::from_secs(RECONNECT_INITIAL_DELAY_SEC),
+            reconnect_max_delay: Duration::from_secs(RECONNECT_MAX_DELAY_SEC),
+            reconnect_max_attempts: None,
+            restart_delay: None,
+            cache_dir: None,
+        }
+    }
+}
+
+pub(crate) fn secs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let secs = f64::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+}
+
+fn opt_secs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    Option::<f64>::deserialize(deserializer)?
+        .map(|secs| Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_str(name: &str, content: &str) -> Result<BackendConfig, Error> {
+        let path =
+            std::env::temp_dir().join(format!("audio-backend-{}-{name}.json", std::process::id()));
+        fs::write(&path, content).unwrap();
+        let config = BackendConfig::from_file(&path);
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn keys_and_defaults() {
+        let config = from_str(
+            "keys",
+            r#"{
+                "discovery": {"static": ["192.168.4.1:80", "[fe80::1]:80"]},
+                "websocket-path": "/ws",
+                "ping-interval": 0.5,
+                "reconnect-max-attempts": 3,
+                "restart-delay": 2
+            }"#,
+        )
+        .unwrap();
+        assert!(matches!(&config.discovery, Discovery::Static(addrs) if addrs.len() == 2));
+        assert_eq!(config.websocket_path, "/ws");
+        assert_eq!(config.ping_interval, Duration::from_millis(500));
+        assert_eq!(config.reconnect_max_attempts, Some(3));
+        assert_eq!(config.restart_delay, Some(Duration::from_secs(2)));
+        assert_eq!(config.service_type, SERVICE_TYPE);
+        assert_eq!(
+            config.connect_timeout,
+            Duration::from_secs(CONNECT_TIMEOUT_SEC)
+        );
+        assert!(config.cache_dir.is_none());
+    }
+
+    #[test]
+    fn mdns_discovery() {
+        let config = from_str("mdns", r#"{"discovery": "mdns"}"#).unwrap();
+        assert!(matches!(config.discovery, Discovery::Mdns));
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        for (name, content) in [
+            ("unknown", r#"{"ping": 2}"#),
+            ("negative", r#"{"ping-interval": -1}"#),
+            ("syntax", "{"),
+        ] {
+            assert!(matches!(from_str(name, content), Err(Error::Config(_))));
+        }
+        let missing = BackendConfig::from_file("/nonexistent/audio-backend.json");
+        assert!(matches!(missing, Err(Error::Config(_))));
+    }
+}
@@ -0,0 +1,233 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use super::data::Track;
+
+// bump whenever the stored tracks change incompatibly, older caches are ignored
+const VERSION: u32 = 1;
+const DIR_NAME: &str = "audio-backend";
+const EXTENSION: &str = "json";
+
+#[derive(Serialize)]
+struct CacheRef<'a> {
+    version: u32,
+    device: &'a str,
+    tracks: &'a [Track],
+}
+
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+#[derive(Deserialize)]
+struct Cache {
+    device: String,
+    tracks: Vec<Track>,
+}
+
+// $XDG_CACHE_HOME/audio-backend or ~/.cache/audio-backend
+pub(crate) fn default_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .map(|p| p.join(DIR_NAME))
+}
+
+// one file per device, named after it
+pub(crate) fn path(dir: &Path, device: &str) -> PathBuf {
+    let name: String = device
+        .trim_end_matches('.')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    dir.join(format!("{name}.{EXTENSION}"))
+}
+
+// the device of the most recently saved cache, to browse before any device is found
+pub(crate) fn last_device(dir: &Path) -> Option<String> {
+    let entries = fs::read_dir(dir).ok()?;
+    let (_, path) = entries
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == EXTENSION))
+        .filter_map(|p| Some((fs::metadata(&p).ok()?.modified().ok()?, p)))
+        .max()?;
+    read(&path).map(|c| c.device)
+}
+
+// devices whose names map to the same file do not get each other's tracks
+pub(crate) fn load(path: &Path, device: &str) -> Vec<Track> {
+    read(path)
+        .filter(|c| c.device == device)
+        .map(|c| c.tracks)
+        .unwrap_or_default()
+}
+
+pub(crate) fn save(path: &Path, device: &str, tracks: &[Track]) -> io::Result<()> {
+    let cache = CacheRef {
+        version: VERSION,
+        device,
+        tracks,
+    };
+    let content = serde_json::to_vec(&cache)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // a crash while writing must not destroy the previous cache
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(tmp, path)
+}
+
+fn read(path: &Path) -> Option<Cache> {
+    let content = match fs::read(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("Could not read cache {}: {e}", path.display());
+            return None;
+        }
+    };
+    // check the version first, the tracks of another version might not parse
+    let cache = serde_json::from_slice::<Header>(&content).and_then(|header| {
+        if header.version == VERSION {
+            serde_json::from_slice::<Cache>(&content).map(Some)
+        } else {
+            debug!(
+                "Ignoring cache {} of version {}",
+                path.display(),
+                header.version
+            );
+            Ok(None)
+        }
+    });
+    cache.unwrap_or_else(|e| {
+        warn!("Could not parse cache {}: {e}", path.display());
+        None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::DirEntry;
+    use crate::database::data::Data;
+    use crate::json::{FileInfo, ListedFile};
+
+    // a fresh directory per test, removed again when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = env::temp_dir().join(format!("{DIR_NAME}-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn synced_data() -> Data {
+        let mut data = Data::default();
+        let file = ListedFile {
+            name: "rock/a.ogg".to_owned(),
+            size: Some(1),
+            md5: Some("abc".to_owned()),
+            ..Default::default()
+        };
+        data.update_file_list(vec![file], true);
+        let info = FileInfo {
+            artist: Some("artist".to_owned()),
+            track: Some(1),
+            ..Default::default()
+        };
+        data.set_file_info("rock/a.ogg", info);
+        data
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = TempDir::new("round-trip");
+        let path = path(&dir.0, "device.local.");
+        save(&path, "device.local.", synced_data().tracks()).unwrap();
+        assert_eq!(last_device(&dir.0).as_deref(), Some("device.local."));
+
+        let mut data = Data::default();
+        data.load("device.local.".to_owned(), load(&path, "device.local."));
+        assert_eq!(data.dir_content(), [DirEntry::Dir("rock".to_owned())]);
+        // the info and the version it was read from are kept
+        let file = ListedFile {
+            name: "rock/a.ogg".to_owned(),
+            md5: Some("abc".to_owned()),
+            ..Default::default()
+        };
+        data.update_file_list(vec![file], true);
+        assert!(data.unsynced_files().is_empty());
+    }
+
+    #[test]
+    fn ignores_other_versions() {
+        let dir = TempDir::new("version");
+        let path = path(&dir.0, "device");
+        save(&path, "device", synced_data().tracks()).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        let older = content.replacen(&format!("\"version\":{VERSION}"), "\"version\":0", 1);
+        assert_ne!(content, older);
+        fs::write(&path, older).unwrap();
+        assert!(load(&path, "device").is_empty());
+        assert!(last_device(&dir.0).is_none());
+        // the tracks of another version need not parse
+        fs::write(&path, r#"{"version":0,"tracks":"unknown"}"#).unwrap();
+        assert!(load(&path, "device").is_empty());
+    }
+
+    #[test]
+    fn missing_or_broken_cache() {
+        let dir = TempDir::new("broken");
+        let path = path(&dir.0, "device");
+        assert!(load(&path, "device").is_empty());
+        fs::create_dir_all(&dir.0).unwrap();
+        fs::write(&path, "{").unwrap();
+        assert!(load(&path, "device").is_empty());
+    }
+
+    #[test]
+    fn ignores_other_device_of_same_file() {
+        let dir = TempDir::new("collision");
+        let path = path(&dir.0, "[fe80::1]:80");
+        assert_eq!(path, super::path(&dir.0, "_fe80__1__80"));
+        save(&path, "[fe80::1]:80", synced_data().tracks()).unwrap();
+        assert_eq!(load(&path, "[fe80::1]:80").len(), 1);
+        assert!(load(&path, "_fe80__1__80").is_empty());
+    }
+
+    #[test]
+    fn file_name_of_device() {
+        let dir = Path::new("/cache");
+        assert_eq!(
+            path(dir, "audio-1._http._tcp.local."),
+            Path::new("/cache/audio-1._http._tcp.local.json")
+        );
+        assert_eq!(
+            path(dir, "[fe80::1]:80"),
+            Path::new("/cache/_fe80__1__80.json")
+        );
+    }
+}
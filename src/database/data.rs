@@ -1,65 +1,131 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
 use super::DirEntry;
-use crate::json::types::FileInfo;
+use crate::json::{FileInfo, ListedFile};
 
-#[derive(PartialEq)]
+#[derive(Default, PartialEq)]
 enum SyncState {
+    // loaded from the cache, not yet seen in a file list of the device
+    #[default]
     Cached,
     Unsynced,
     Synced,
 }
 
+#[derive(Serialize, Deserialize)]
 struct TrackInfo {
-    genre: String,
-    artist: String,
-    album: String,
-    title: String,
-    track: u16,
-    duration: u16,
+    genre: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    title: Option<String>,
+    track: Option<u16>,
+    duration: Option<u16>,
+}
+
+// what the device tells about a file to detect changes
+#[derive(Default, Serialize, Deserialize)]
+struct Version {
+    size: Option<u32>,
+    mtime: Option<u64>,
+    md5: Option<String>,
 }
 
-struct Track {
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Track {
     filename: Vec<String>,
+    #[serde(skip)]
     sync_state: SyncState,
     info: Option<TrackInfo>,
+    // the version of the file the info was read from
+    #[serde(default)]
+    version: Version,
+    // the version in the current file list, it becomes `version` once the info is read
+    #[serde(skip)]
+    listed: Version,
 }
 
-pub struct Data {
+#[derive(Default)]
+pub(crate) struct Data {
+    // the device whose tracks these are, the key of the cache
+    device: Option<String>,
     current_dir: Vec<String>,
     tracks: Vec<Track>,
-    num_to_sync: usize,
-    num_synced: usize,
+    // true while the file list of a sync is being received
+    updating: bool,
 }
 
-impl Data {
-    pub fn new() -> Self {
+impl From<FileInfo> for TrackInfo {
+    fn from(info: FileInfo) -> Self {
         Self {
-            current_dir: Vec::new(),
-            tracks: Vec::new(),
-            num_to_sync: 0,
-            num_synced: 0,
+            genre: info.genre,
+            artist: info.artist,
+            album: info.album,
+            title: info.title,
+            track: info.track,
+            duration: info.duration,
         }
     }
+}
 
-    pub fn save(&self) {
-        // TODO
+impl From<&ListedFile> for Version {
+    fn from(file: &ListedFile) -> Self {
+        Self {
+            size: file.size,
+            mtime: file.mtime,
+            md5: file.md5.clone(),
+        }
     }
+}
 
-    pub fn dir_current(&self) -> String {
+impl Version {
+    // the md5 decides if both have one, else mtime and size, a file without either is changed
+    fn unchanged(&self, other: &Self) -> bool {
+        match (&self.md5, &other.md5) {
+            (Some(a), Some(b)) => a == b,
+            _ => {
+                self.mtime.is_some()
+                    && self.size.is_some()
+                    && (self.mtime, self.size) == (other.mtime, other.size)
+            }
+        }
+    }
+}
+
+impl Data {
+    pub(crate) fn device(&self) -> Option<&str> {
+        self.device.as_deref()
+    }
+
+    pub(crate) fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    // replaces the tracks by those cached for another device
+    pub(crate) fn load(&mut self, device: String, tracks: Vec<Track>) {
+        self.device = Some(device);
+        self.current_dir.clear();
+        self.tracks = tracks;
+        self.updating = false;
+    }
+
+    pub(crate) fn dir_current(&self) -> String {
         match self.current_dir.last() {
             Some(d) => String::from(d),
             None => String::new(),
         }
     }
 
-    pub fn dir_up(&mut self) {
+    pub(crate) fn dir_up(&mut self) {
         self.current_dir.pop();
     }
 
-    pub fn dir_enter(&mut self, dir: &str) {
+    pub(crate) fn dir_enter(&mut self, dir: &str) {
         self.current_dir.push(String::from(dir));
     }
 
-    pub fn dir_content(&self) -> Vec<DirEntry> {
+    pub(crate) fn dir_content(&self) -> Vec<DirEntry> {
         let mut list: Vec<DirEntry> = self
             .tracks
             .iter()
@@ -82,58 +148,147 @@ impl Data {
         list
     }
 
-    pub fn update_file_list(&mut self, lst: Vec<String>, last: bool) {
+    // returns the number of tracks
+    pub(crate) fn update_file_list(&mut self, lst: Vec<ListedFile>, last: bool) -> usize {
+        if !self.updating {
+            // tracks that are not in the new list are removed at its end
+            for t in &mut self.tracks {
+                t.sync_state = SyncState::Cached;
+            }
+            self.updating = true;
+        }
         for f in lst {
-            let name = f.split("/").map(str::to_string).collect();
+            let listed = Version::from(&f);
+            let name = f.name.split('/').map(str::to_owned).collect();
             if let Some(t) = self.tracks.iter_mut().find(|t| t.filename == name) {
-                // TODO
-                // if date ok, set to synced instead
-                t.sync_state = SyncState::Unsynced;
+                // only new or changed files need their info again
+                t.sync_state = if t.info.is_some() && t.version.unchanged(&listed) {
+                    SyncState::Synced
+                } else {
+                    SyncState::Unsynced
+                };
+                t.listed = listed;
             } else {
                 self.tracks.push(Track {
                     filename: name,
                     sync_state: SyncState::Unsynced,
                     info: None,
+                    version: Version::default(),
+                    listed,
                 });
             }
         }
         if last {
             self.tracks.retain(|t| t.sync_state != SyncState::Cached);
-            self.num_to_sync = self
-                .tracks
-                .iter()
-                .filter(|t| t.sync_state == SyncState::Unsynced)
-                .count();
-            self.num_synced = 0;
+            self.updating = false;
         }
+        self.tracks.len()
     }
 
-    pub fn get_unsynced_file(&self) -> Option<String> {
-        let t = self
-            .tracks
+    // the new or changed tracks, whose info has to be read
+    pub(crate) fn unsynced_files(&self) -> Vec<String> {
+        self.tracks
             .iter()
             .filter(|t| {
-                t.filename.last().unwrap().ends_with(".ogg") && t.sync_state == SyncState::Unsynced
+                t.sync_state == SyncState::Unsynced
+                    && t.filename.last().is_some_and(|f| {
+                        Path::new(f)
+                            .extension()
+                            .is_some_and(|e| e.eq_ignore_ascii_case("ogg"))
+                    })
             })
-            .next();
-        match t {
-            Some(t) => Some(t.filename.join("/")),
-            None => None,
-        }
+            .map(|t| t.filename.join("/"))
+            .collect()
     }
 
-    pub fn set_file_info(&mut self, info: FileInfo) {
-        if let Some(t) = self
-            .tracks
-            .iter_mut()
-            .find(|t| t.filename.join("/") == info.filename)
-        {
+    pub(crate) fn set_file_info(&mut self, filename: &str, info: FileInfo) {
+        let filename: Vec<&str> = filename.split('/').collect();
+        if let Some(t) = self.tracks.iter_mut().find(|t| t.filename == filename) {
             t.sync_state = SyncState::Synced;
-            self.num_synced += 1;
+            t.info = Some(info.into());
+            t.version = std::mem::take(&mut t.listed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(size: Option<u32>, mtime: Option<u64>, md5: Option<&str>) -> Version {
+        Version {
+            size,
+            mtime,
+            md5: md5.map(str::to_owned),
+        }
+    }
+
+    fn listed(name: &str, md5: &str) -> ListedFile {
+        ListedFile {
+            name: name.to_owned(),
+            md5: Some(md5.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn info() -> FileInfo {
+        FileInfo {
+            title: Some("title".to_owned()),
+            ..Default::default()
         }
     }
 
-    pub fn sync_stats(&self) -> (usize, usize) {
-        (self.num_synced, self.num_to_sync)
+    #[test]
+    fn unchanged_by_md5() {
+        let a = version(Some(1), Some(2), Some("abc"));
+        assert!(a.unchanged(&version(Some(3), Some(4), Some("abc"))));
+        assert!(!a.unchanged(&version(Some(1), Some(2), Some("def"))));
+    }
+
+    #[test]
+    fn unchanged_by_mtime_and_size() {
+        let a = version(Some(1), Some(2), None);
+        assert!(a.unchanged(&version(Some(1), Some(2), Some("abc"))));
+        assert!(!a.unchanged(&version(Some(1), Some(3), None)));
+        assert!(!a.unchanged(&version(Some(2), Some(2), None)));
+    }
+
+    #[test]
+    fn changed_without_version() {
+        assert!(!Version::default().unchanged(&Version::default()));
+        let a = version(None, Some(2), None);
+        assert!(!a.unchanged(&version(None, Some(2), None)));
+    }
+
+    #[test]
+    fn update_file_list_marks_changed_tracks() {
+        let mut data = Data::default();
+        let n = data.update_file_list(vec![listed("a.ogg", "1"), listed("dir/b.ogg", "2")], true);
+        assert_eq!(n, 2);
+        assert_eq!(data.unsynced_files(), ["a.ogg", "dir/b.ogg"]);
+        data.set_file_info("a.ogg", info());
+        data.set_file_info("dir/b.ogg", info());
+        assert!(data.unsynced_files().is_empty());
+
+        data.update_file_list(vec![listed("a.ogg", "1"), listed("dir/b.ogg", "3")], true);
+        assert!(data.tracks[0].sync_state == SyncState::Synced);
+        assert_eq!(data.unsynced_files(), ["dir/b.ogg"]);
+    }
+
+    #[test]
+    fn update_file_list_removes_missing_tracks() {
+        let mut data = Data::default();
+        data.update_file_list(vec![listed("a.ogg", "1"), listed("b.ogg", "2")], true);
+        // the list may arrive in parts, tracks are only removed after the last one
+        assert_eq!(data.update_file_list(vec![listed("b.ogg", "2")], false), 2);
+        assert_eq!(data.update_file_list(vec![listed("c.ogg", "3")], true), 2);
+        assert_eq!(data.unsynced_files(), ["b.ogg", "c.ogg"]);
+    }
+
+    #[test]
+    fn unsynced_files_are_tracks() {
+        let mut data = Data::default();
+        data.update_file_list(vec![listed("a.OGG", "1"), listed("b.txt", "2")], true);
+        assert_eq!(data.unsynced_files(), ["a.OGG"]);
     }
 }
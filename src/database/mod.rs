@@ -0,0 +1,102 @@
+use crate::json::{FileInfo, ListedFile};
+use data::Data;
+use log::{debug, error};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+mod cache;
+mod data;
+
+#[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum DirEntry {
+    Dir(String),
+    File(String),
+}
+
+/// The tracks on the device, filled by a file sync and browsable like a directory tree.
+///
+/// The tracks are cached per device, so the library of the last device can be browsed while
+/// it is offline.
+#[derive(Clone)]
+pub struct Database {
+    data: Arc<Mutex<Data>>,
+    // no caching if None
+    cache_dir: Option<PathBuf>,
+}
+
+impl Database {
+    pub(crate) fn new(cache_dir: Option<PathBuf>) -> Self {
+        let database = Self {
+            data: Arc::new(Mutex::new(Data::default())),
+            cache_dir: cache_dir.or_else(cache::default_dir),
+        };
+        if let Some(device) = database.cache_dir.as_deref().and_then(cache::last_device) {
+            database.select_device(device);
+        }
+        database
+    }
+
+    // loads the cached tracks of `device` unless they are loaded already
+    pub(crate) fn select_device(&self, device: String) {
+        let mut data = self.lock();
+        if data.device() == Some(device.as_str()) {
+            return;
+        }
+        let tracks = self
+            .cache_dir
+            .as_deref()
+            .map(|dir| cache::load(&cache::path(dir, &device), &device))
+            .unwrap_or_default();
+        debug!("Loaded {} cached tracks of {device}", tracks.len());
+        data.load(device, tracks);
+    }
+
+    pub(crate) fn save(&self) {
+        let data = self.lock();
+        if let (Some(dir), Some(device)) = (&self.cache_dir, data.device()) {
+            let path = cache::path(dir, device);
+            if let Err(e) = cache::save(&path, device, data.tracks()) {
+                error!("Could not save cache {}: {e}", path.display());
+            }
+        }
+    }
+
+    pub fn dir_current(&self) -> String {
+        let data = self.lock();
+        data.dir_current()
+    }
+
+    pub fn dir_up(&self) {
+        let mut data = self.lock();
+        data.dir_up();
+    }
+
+    pub fn dir_enter(&self, dir: &str) {
+        let mut data = self.lock();
+        data.dir_enter(dir);
+    }
+
+    pub fn dir_content(&self) -> Vec<DirEntry> {
+        let data = self.lock();
+        data.dir_content()
+    }
+
+    pub(crate) fn update_file_list(&self, lst: Vec<ListedFile>, last: bool) -> usize {
+        let mut data = self.lock();
+        data.update_file_list(lst, last)
+    }
+
+    pub(crate) fn unsynced_files(&self) -> Vec<String> {
+        let data = self.lock();
+        data.unsynced_files()
+    }
+
+    pub(crate) fn set_file_info(&self, filename: &str, info: FileInfo) {
+        let mut data = self.lock();
+        data.set_file_info(filename, info);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Data> {
+        self.data.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
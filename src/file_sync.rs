@@ -0,0 +1,342 @@
+use std::collections::{HashMap, VecDeque};
+
+use log::debug;
+
+use crate::database::Database;
+use crate::json::{File, FileInfo, ListedFile};
+
+// requests sent at the same time while walking the directories or fetching track infos
+const MAX_IN_FLIGHT: usize = 4;
+
+// syncs the database with the device in two phases: it walks the directory tree with
+// get-file-list and stores the files found once all directories are listed, then it fetches the
+// info of every new or changed track with get-file-info
+//
+// the listing only has names, so the md5 of the files reported by get-info-spiflash is used to
+// detect changes
+pub(crate) struct FileSync {
+    database: Database,
+    // incremented with every sync, responses of an aborted sync are ignored
+    walk: u32,
+    phase: Phase,
+    // get-info-spiflash is still to request
+    flash: bool,
+    // the files of the SPI flash by name
+    md5s: HashMap<String, File>,
+    // directories still to list, "" is the root
+    dirs: VecDeque<String>,
+    // tracks still to fetch the info of
+    infos: VecDeque<String>,
+    in_flight: usize,
+    files: Vec<ListedFile>,
+    tracks: usize,
+}
+
+#[derive(PartialEq)]
+enum Phase {
+    Idle,
+    List,
+    Info,
+}
+
+pub(crate) enum Request {
+    Flash,
+    List(String),
+    Info(String),
+}
+
+impl FileSync {
+    pub(crate) fn new(database: Database) -> Self {
+        Self {
+            database,
+            walk: 0,
+            phase: Phase::Idle,
+            flash: false,
+            md5s: HashMap::new(),
+            dirs: VecDeque::new(),
+            infos: VecDeque::new(),
+            in_flight: 0,
+            files: Vec::new(),
+            tracks: 0,
+        }
+    }
+
+    pub(crate) fn start(&mut self) {
+        self.walk = self.walk.wrapping_add(1);
+        self.phase = Phase::List;
+        self.flash = true;
+        self.md5s.clear();
+        self.dirs = VecDeque::from([String::new()]);
+        self.infos.clear();
+        self.in_flight = 0;
+        self.files.clear();
+    }
+
+    // the next request with the sync it belongs to, if another request may be sent
+    pub(crate) fn next(&mut self) -> Option<(u32, Request)> {
+        if self.in_flight >= MAX_IN_FLIGHT {
+            return None;
+        }
+        let request = match self.phase {
+            Phase::Idle => None,
+            Phase::List if self.flash => {
+                self.flash = false;
+                Some(Request::Flash)
+            }
+            Phase::List => self.dirs.pop_front().map(Request::List),
+            Phase::Info => self.infos.pop_front().map(Request::Info),
+        }?;
+        self.in_flight += 1;
+        Some((self.walk, request))
+    }
+
+    // the files and subdirectories of `dir`, returns the number of tracks when the sync is done
+    pub(crate) fn listed(
+        &mut self,
+        walk: u32,
+        dir: &str,
+        dirs: Vec<String>,
+        files: Vec<ListedFile>,
+    ) -> Option<usize> {
+        if !self.is_running(walk) {
+            return None;
+        }
+        self.in_flight -= 1;
+        self.dirs.extend(dirs.iter().map(|d| join(dir, d)));
+        self.files.extend(files.into_iter().map(|f| ListedFile {
+            name: join(dir, &f.name),
+            ..f
+        }));
+        self.list_done()
+    }
+
+    // the files of the SPI flash, empty if the device could not report them
+    pub(crate) fn flash(&mut self, walk: u32, files: Vec<File>) -> Option<usize> {
+        if !self.is_running(walk) {
+            return None;
+        }
+        self.in_flight -= 1;
+        self.md5s = files
+            .into_iter()
+            .map(|f| (f.name.trim_matches('/').to_owned(), f))
+            .collect();
+        self.list_done()
+    }
+
+    fn list_done(&mut self) -> Option<usize> {
+        if !self.dirs.is_empty() || self.in_flight > 0 {
+            return None;
+        }
+        for f in &mut self.files {
+            if f.md5.is_none()
+                && let Some(flash) = self.md5s.get(&f.name)
+            {
+                f.md5 = Some(flash.md5.clone());
+                f.size.get_or_insert(flash.size);
+            }
+        }
+        self.tracks = self
+            .database
+            .update_file_list(std::mem::take(&mut self.files), true);
+        self.infos = self.database.unsynced_files().into();
+        debug!(
+            "{} tracks, {} new or changed",
+            self.tracks,
+            self.infos.len()
+        );
+        self.phase = Phase::Info;
+        self.finish()
+    }
+
+    // the info of the track `filename` as requested, `None` if the device could not read it
+    pub(crate) fn info(
+        &mut self,
+        walk: u32,
+        filename: &str,
+        info: Option<FileInfo>,
+    ) -> Option<usize> {
+        if !self.is_running(walk) {
+            return None;
+        }
+        self.in_flight -= 1;
+        if let Some(info) = info {
+            self.database.set_file_info(filename, info);
+        }
+        self.finish()
+    }
+
+    // stops the sync after a failed request, returns false if it was stopped already
+    pub(crate) fn abort(&mut self, walk: u32) -> bool {
+        if !self.is_running(walk) {
+            return false;
+        }
+        self.phase = Phase::Idle;
+        self.dirs.clear();
+        self.infos.clear();
+        self.files.clear();
+        self.md5s.clear();
+        // keep the infos fetched so far
+        self.database.save();
+        true
+    }
+
+    fn finish(&mut self) -> Option<usize> {
+        if !self.infos.is_empty() || self.in_flight > 0 {
+            return None;
+        }
+        self.phase = Phase::Idle;
+        self.database.save();
+        Some(self.tracks)
+    }
+
+    fn is_running(&self, walk: u32) -> bool {
+        self.phase != Phase::Idle && walk == self.walk
+    }
+}
+
+// get-file-list names the entries relative to the listed directory
+fn join(dir: &str, name: &str) -> String {
+    let name = name.trim_matches('/');
+    if dir.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // no cache is loaded or saved without a device
+    fn database() -> Database {
+        Database::new(Some(
+            std::env::temp_dir().join("audio-backend-test-no-cache"),
+        ))
+    }
+
+    fn file(name: &str) -> ListedFile {
+        ListedFile {
+            name: name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn flash_file(name: &str, md5: &str) -> File {
+        File {
+            name: name.to_owned(),
+            content_type: "audio/ogg".to_owned(),
+            size: 1,
+            md5: md5.to_owned(),
+        }
+    }
+
+    fn requests(sync: &mut FileSync) -> Vec<(u32, Request)> {
+        std::iter::from_fn(|| sync.next()).collect()
+    }
+
+    fn list(requests: &[(u32, Request)]) -> Vec<&str> {
+        requests
+            .iter()
+            .filter_map(|(_, r)| match r {
+                Request::List(dir) => Some(dir.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn infos(requests: &[(u32, Request)]) -> Vec<&str> {
+        requests
+            .iter()
+            .filter_map(|(_, r)| match r {
+                Request::Info(filename) => Some(filename.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn walks_all_directories() {
+        let mut sync = FileSync::new(database());
+        sync.start();
+        let first = requests(&mut sync);
+        assert!(matches!(first[0].1, Request::Flash));
+        assert_eq!(list(&first), [""]);
+        let walk = first[0].0;
+
+        assert_eq!(sync.flash(walk, Vec::new()), None);
+        let dirs = vec!["rock".to_owned(), "jazz".to_owned()];
+        assert_eq!(sync.listed(walk, "", dirs, vec![file("top.ogg")]), None);
+        let second = requests(&mut sync);
+        assert_eq!(list(&second), ["rock", "jazz"]);
+
+        let dirs = vec!["old".to_owned()];
+        assert_eq!(sync.listed(walk, "rock", dirs, vec![file("r.ogg")]), None);
+        let files = vec![file("j.ogg")];
+        assert_eq!(sync.listed(walk, "jazz", Vec::new(), files), None);
+        assert_eq!(list(&requests(&mut sync)), ["rock/old"]);
+        assert_eq!(sync.listed(walk, "rock/old", Vec::new(), Vec::new()), None);
+
+        let fetch = requests(&mut sync);
+        assert_eq!(infos(&fetch), ["top.ogg", "rock/r.ogg", "jazz/j.ogg"]);
+        for filename in infos(&fetch) {
+            let done = sync.info(walk, filename, Some(FileInfo::default()));
+            assert_eq!(done.is_some(), filename == "jazz/j.ogg");
+        }
+        assert_eq!(sync.info(walk, "top.ogg", None), None);
+    }
+
+    #[test]
+    fn bounds_requests_in_flight() {
+        let mut sync = FileSync::new(database());
+        sync.start();
+        let walk = requests(&mut sync)[0].0;
+        sync.flash(walk, Vec::new());
+        let dirs = (0..10).map(|i| i.to_string()).collect();
+        sync.listed(walk, "", dirs, Vec::new());
+        assert_eq!(requests(&mut sync).len(), MAX_IN_FLIGHT);
+        sync.listed(walk, "0", Vec::new(), Vec::new());
+        assert_eq!(list(&requests(&mut sync)), ["4"]);
+    }
+
+    #[test]
+    fn skips_unchanged_tracks_by_flash_md5() {
+        let mut sync = FileSync::new(database());
+        for (md5, fetched) in [("1", true), ("1", false), ("2", true)] {
+            sync.start();
+            let walk = requests(&mut sync)[0].0;
+            sync.flash(walk, vec![flash_file("/a.ogg", md5)]);
+            let done = sync.listed(walk, "", Vec::new(), vec![file("a.ogg")]);
+            let fetch = requests(&mut sync);
+            assert_eq!(infos(&fetch).len(), usize::from(fetched));
+            assert_eq!(done.is_some(), !fetched);
+            if fetched {
+                assert_eq!(sync.info(walk, "a.ogg", Some(FileInfo::default())), Some(1));
+            }
+        }
+    }
+
+    #[test]
+    fn ignores_aborted_walks() {
+        let mut sync = FileSync::new(database());
+        sync.start();
+        let old = requests(&mut sync)[0].0;
+        assert!(sync.abort(old));
+        assert!(!sync.abort(old));
+        sync.start();
+        let walk = requests(&mut sync)[0].0;
+        assert_ne!(old, walk);
+        assert_eq!(sync.flash(old, Vec::new()), None);
+        assert_eq!(sync.listed(old, "", Vec::new(), Vec::new()), None);
+        assert_eq!(sync.in_flight, 2);
+    }
+
+    #[test]
+    fn joins_relative_names() {
+        assert_eq!(join("", "a.ogg"), "a.ogg");
+        assert_eq!(join("rock", "a.ogg"), "rock/a.ogg");
+        // a subdirectory named like its parent
+        assert_eq!(join("Live", "Live/x.ogg"), "Live/Live/x.ogg");
+        assert_eq!(join("rock/old", "old/a.ogg"), "rock/old/old/a.ogg");
+    }
+}
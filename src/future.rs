@@ -0,0 +1,174 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+
+use futures_core::Stream;
+
+use crate::subscription::Subscribers;
+use crate::{Error, Event, EventKind, RequestHandle, Subscription};
+
+/// A [`Stream`] of backend events, returned by [`Backend::events`](crate::Backend::events).
+///
+/// It does not depend on a particular async runtime and ends when the backend is dropped.
+pub struct EventStream {
+    queue: Arc<Mutex<Queue>>,
+    _subscription: Subscription,
+}
+
+#[derive(Default)]
+struct Queue {
+    events: VecDeque<Event>,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+// fills the queue on the backend thread, it is dropped together with the backend
+struct Feeder(Arc<Mutex<Queue>>);
+
+impl EventStream {
+    pub(crate) fn new(subscribers: &Arc<Subscribers>, kinds: Option<Vec<EventKind>>) -> Self {
+        let queue = Arc::new(Mutex::new(Queue::default()));
+        let feeder = Feeder(queue.clone());
+        let subscription =
+            subscribers.callback(kinds, Box::new(move |evt| feeder.push(evt.clone())));
+        Self {
+            queue,
+            _subscription: subscription,
+        }
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Event;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        let mut queue = lock(&self.queue);
+        if let Some(evt) = queue.events.pop_front() {
+            Poll::Ready(Some(evt))
+        } else if queue.closed {
+            Poll::Ready(None)
+        } else {
+            queue.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl Feeder {
+    fn push(&self, evt: Event) {
+        let mut queue = lock(&self.0);
+        queue.events.push_back(evt);
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for Feeder {
+    fn drop(&mut self) {
+        let mut queue = lock(&self.0);
+        queue.closed = true;
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Future for RequestHandle<T> {
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.receiver.poll_recv(cx).map(|res| {
+            self.done.set(true);
+            res.unwrap_or(Err(Error::BackendDead))
+        })
+    }
+}
+
+fn lock(queue: &Mutex<Queue>) -> MutexGuard<'_, Queue> {
+    queue.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::task::Wake;
+
+    use super::*;
+    use crate::common::oneshot;
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicU32);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn waker(counter: &Arc<CountingWaker>) -> Waker {
+        Waker::from(counter.clone())
+    }
+
+    #[test]
+    fn request_handle_resolves() {
+        let (sender, receiver) = oneshot::channel();
+        let mut handle = RequestHandle {
+            receiver,
+            done: Cell::new(false),
+        };
+        let counter = Arc::new(CountingWaker::default());
+        let waker = waker(&counter);
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut handle).poll(&mut cx).is_pending());
+        sender.send(Ok(5));
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            Pin::new(&mut handle).poll(&mut cx),
+            Poll::Ready(Ok(5))
+        ));
+        assert!(handle.try_get().is_none());
+    }
+
+    #[test]
+    fn request_handle_of_dropped_request() {
+        let (sender, receiver) = oneshot::channel::<Result<(), Error>>();
+        let mut handle = RequestHandle {
+            receiver,
+            done: Cell::new(false),
+        };
+        drop(sender);
+        let waker = Waker::noop();
+        let mut cx = Context::from_waker(waker);
+        assert!(matches!(
+            Pin::new(&mut handle).poll(&mut cx),
+            Poll::Ready(Err(Error::BackendDead))
+        ));
+    }
+
+    #[test]
+    fn event_stream() {
+        let subscribers = Arc::new(Subscribers::default());
+        let mut stream = EventStream::new(&subscribers, Some(vec![EventKind::Connected]));
+        let counter = Arc::new(CountingWaker::default());
+        let waker = waker(&counter);
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut stream).poll_next(&mut cx).is_pending());
+        subscribers.send(Event::Disconnected);
+        subscribers.send(Event::Connected);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            Pin::new(&mut stream).poll_next(&mut cx),
+            Poll::Ready(Some(Event::Connected))
+        ));
+        assert!(Pin::new(&mut stream).poll_next(&mut cx).is_pending());
+        // the stream ends with the backend
+        drop(subscribers);
+        assert!(matches!(
+            Pin::new(&mut stream).poll_next(&mut cx),
+            Poll::Ready(None)
+        ));
+    }
+}
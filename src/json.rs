@@ -1,18 +1,17 @@
-use log::error;
-use serde::Deserialize;
-use serde_json::json;
+use std::time::{Duration, Instant};
+
+use log::{error, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 use crate::common::jsonrpc;
+use crate::common::jsonrpc::ExecError;
+use crate::{Event, RemoteError};
 
-const GET_INFO_CONNECTION: &str = "get-info-connection";
-const GET_INFO_ABOUT: &str = "get-info-about";
-const GET_INFO_MEMORY: &str = "get-info-memory";
-const GET_INFO_SPIFLASH: &str = "get-info-spiflash";
-const GET_WIFI_SCAN_RESULT: &str = "get-wifi-scan-result";
-const GET_WIFI_NETWORK_LIST: &str = "get-wifi-network-list";
-const SET_WIFI_NETWORK: &str = "set-wifi-network";
-const DELETE_WIFI_NETWORK: &str = "delete-wifi-network";
-const GET_FILE_LIST: &str = "get-file-list";
+const TRACK_CHANGED: &str = "track-changed";
+const VOLUME_CHANGED: &str = "volume-changed";
+const STORAGE_CHANGED: &str = "storage-changed";
 
 #[derive(Default)]
 pub(crate) struct Handler {
@@ -20,31 +19,166 @@ pub(crate) struct Handler {
 }
 
 pub(crate) enum Message {
-    Response(Response),
-    //Notification,
-}
-
-pub(crate) enum Response {
-    InfoConnection(Result<Connection, jsonrpc::ExecError>),
-    InfoAbout(Result<About, jsonrpc::ExecError>),
-    InfoMemory(Result<Memory, jsonrpc::ExecError>),
-    InfoSPIFlash(Result<SPIFlash, jsonrpc::ExecError>),
-    ScanResult(Result<Vec<ScannedNetwork>, jsonrpc::ExecError>),
-    NetworkList(Result<Vec<StoredNetwork>, jsonrpc::ExecError>),
-    SetNetwork(Result<Empty, jsonrpc::ExecError>),
-    DeleteNetwork(Result<Empty, jsonrpc::ExecError>),
-    FileList(Result<FileList, jsonrpc::ExecError>),
-}
-
-//#[derive(Deserialize)]
-//#[serde(untagged)]
-//pub(crate) enum RpcResult {
-//   InfoCon(InfoCon),
-//  ScanResult(Vec<Network>),
-// NetworkList(Vec<String>),
-//FileList(FileList),
-//FileInfo(FileInfo),
-//}
+    Response(u32, Result<Value, ExecError>),
+    Notification(Notification),
+    Invalid(Option<u32>, String),
+}
+
+pub(crate) enum Notification {
+    Track(Track),
+    Volume(Volume),
+    Storage(Storage),
+}
+
+pub(crate) type Parser = fn(Result<Value, ExecError>) -> Result<Event, String>;
+
+pub(crate) trait RpcMethod {
+    const NAME: &'static str;
+    type Params: Serialize;
+    type Result: DeserializeOwned;
+
+    fn params(params: &Self::Params) -> Option<Value> {
+        Some(serde_json::to_value(params).unwrap()).filter(|v| !v.is_null())
+    }
+
+    fn result(data: Result<Value, ExecError>) -> Result<Result<Self::Result, ExecError>, String> {
+        match data {
+            Ok(v) => match serde_json::from_value(v) {
+                Ok(o) => Ok(Ok(o)),
+                Err(e) => {
+                    let error = format!("Could not parse {} response: {e}", Self::NAME);
+                    error!("{error}");
+                    Err(error)
+                }
+            },
+            Err(e) => Ok(Err(e)),
+        }
+    }
+}
+
+/// A method whose result is reported to the application as an [`Event`].
+pub(crate) trait RpcEvent: RpcMethod {
+    type Output: Clone + Send + 'static;
+
+    fn output(result: Self::Result) -> Self::Output;
+
+    fn event(res: Result<Self::Output, RemoteError>) -> Event;
+
+    fn extract(evt: &Event) -> Option<Result<Self::Output, RemoteError>>;
+
+    fn parse(data: Result<Value, ExecError>) -> Result<Event, String> {
+        let res = Self::result(data)?;
+        if let Err(e) = &res {
+            error!("{} failed: {e}", Self::NAME);
+        }
+        Ok(Self::event(
+            res.map(Self::output).map_err(RemoteError::from),
+        ))
+    }
+}
+
+// implements the methods declared in rpc_methods!
+macro_rules! rpc_impls {
+    ($($name:ident($method:literal, $params:ty) -> $result:ty => $event:ident($output:ty) = $convert:expr;)*) => {$(
+        pub(crate) struct $name;
+
+        impl RpcMethod for $name {
+            const NAME: &'static str = $method;
+            type Params = $params;
+            type Result = $result;
+        }
+
+        impl RpcEvent for $name {
+            type Output = $output;
+
+            fn output(result: $result) -> $output {
+                let convert: fn($result) -> $output = $convert;
+                convert(result)
+            }
+
+            fn event(res: Result<$output, RemoteError>) -> Event {
+                Event::$event(res)
+            }
+
+            fn extract(evt: &Event) -> Option<Result<$output, RemoteError>> {
+                match evt {
+                    Event::$event(res) => Some(res.clone()),
+                    _ => None,
+                }
+            }
+        }
+    )*};
+}
+
+// every method whose result is reported as an event, declared as
+// name(method, params) -> wire result => event variant(public result) = conversion
+//
+// the list is handed to `$then`, which is rpc_impls! here and the event definition in lib.rs,
+// so only the public method of the backend is still written by hand
+macro_rules! rpc_methods {
+    ($then:ident) => {
+        $then! {
+            GetInfoConnection("get-info-connection", ()) -> Connection
+                => InfoConnection(crate::Connection) = From::from;
+            GetInfoAbout("get-info-about", ()) -> About
+                => InfoAbout(crate::About) = From::from;
+            GetInfoMemory("get-info-memory", ()) -> Memory
+                => InfoMemory(crate::Memory) = From::from;
+            GetInfoSPIFlash("get-info-spiflash", ()) -> SPIFlash
+                => InfoSPIFlash(crate::SPIFlash) = From::from;
+            GetWifiScanResult("get-wifi-scan-result", ()) -> Vec<ScannedNetwork>
+                => ScanResult(Vec<crate::Network>) = |list| list.into_iter().map(From::from).collect();
+            GetWifiNetworkList("get-wifi-network-list", ()) -> Vec<StoredNetwork>
+                => NetworkList(Vec<String>) = |list| list.into_iter().map(|n| n.ssid).collect();
+            SetWifiNetwork("set-wifi-network", SetNetworkParams) -> Empty
+                => SetNetwork(()) = |_| ();
+            DeleteWifiNetwork("delete-wifi-network", DeleteNetworkParams) -> Empty
+                => DeleteNetwork(()) = |_| ();
+        }
+    };
+}
+
+pub(crate) use rpc_methods;
+
+rpc_methods!(rpc_impls);
+
+// the file sync handles the responses of these itself, they are not reported as events
+pub(crate) struct GetFileList;
+
+impl RpcMethod for GetFileList {
+    const NAME: &'static str = "get-file-list";
+    type Params = Option<FileListParams>;
+    type Result = FileList;
+}
+
+pub(crate) struct GetFileInfo;
+
+impl RpcMethod for GetFileInfo {
+    const NAME: &'static str = "get-file-info";
+    type Params = FileInfoParams;
+    type Result = FileInfo;
+}
+
+#[derive(Serialize)]
+pub(crate) struct SetNetworkParams {
+    pub ssid: String,
+    pub key: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct DeleteNetworkParams {
+    pub ssid: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct FileListParams {
+    pub path: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct FileInfoParams {
+    pub filename: String,
+}
 
 #[derive(Deserialize)]
 pub(crate) struct Connection {
@@ -102,7 +236,50 @@ pub(crate) struct StoredNetwork {
 #[derive(Deserialize)]
 pub(crate) struct FileList {
     pub dirs: Option<Vec<String>>,
-    pub files: Option<Vec<String>>,
+    pub files: Option<Vec<FileEntry>>,
+}
+
+// a file is either listed by name only or with the data to detect changes
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum FileEntry {
+    Name(String),
+    File(ListedFile),
+}
+
+#[derive(Default, Deserialize)]
+pub(crate) struct ListedFile {
+    pub name: String,
+    pub size: Option<u32>,
+    pub mtime: Option<u64>,
+    pub md5: Option<String>,
+}
+
+// a track may lack any of its tags
+#[derive(Default, Deserialize)]
+pub(crate) struct FileInfo {
+    pub genre: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub title: Option<String>,
+    pub track: Option<u16>,
+    pub duration: Option<u16>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Track {
+    pub file: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Volume {
+    pub volume: u8,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Storage {
+    pub total: u32,
+    pub free: u32,
 }
 
 #[allow(clippy::empty_structs_with_brackets)]
@@ -110,221 +287,228 @@ pub(crate) struct FileList {
 pub(crate) struct Empty {}
 
 impl Handler {
-    pub(crate) fn get_info_connection(&self) -> String {
-        self.jsonrpc.build_request(GET_INFO_CONNECTION, None)
+    pub(crate) fn build_request(
+        &self,
+        method: &'static str,
+        params: Option<Value>,
+        timeout: Option<Duration>,
+    ) -> (u32, String) {
+        self.jsonrpc.build_request(method, params, timeout)
     }
 
-    pub(crate) fn get_info_about(&self) -> String {
-        self.jsonrpc.build_request(GET_INFO_ABOUT, None)
+    pub(crate) fn request<M: RpcMethod>(&self, params: &M::Params) -> (u32, String) {
+        self.jsonrpc.build_request(M::NAME, M::params(params), None)
     }
 
-    pub(crate) fn get_info_memory(&self) -> String {
-        self.jsonrpc.build_request(GET_INFO_MEMORY, None)
+    pub(crate) fn build_batch(&self, methods: Vec<&'static str>) -> (Vec<u32>, String) {
+        self.jsonrpc
+            .build_batch(methods.into_iter().map(|method| (method, None)).collect())
     }
 
-    pub(crate) fn get_info_spiflash(&self) -> String {
-        self.jsonrpc.build_request(GET_INFO_SPIFLASH, None)
+    pub(crate) fn set_timeout(&self, timeout: Duration) {
+        self.jsonrpc.set_timeout(timeout);
     }
 
-    pub(crate) fn get_wifi_scan_result(&self) -> String {
-        self.jsonrpc.build_request(GET_WIFI_SCAN_RESULT, None)
+    pub(crate) fn expire(&self) -> Vec<u32> {
+        self.jsonrpc.expire()
     }
 
-    pub(crate) fn get_wifi_network_list(&self) -> String {
-        self.jsonrpc.build_request(GET_WIFI_NETWORK_LIST, None)
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.jsonrpc.next_deadline()
     }
 
-    pub(crate) fn set_wifi_network(&self, ssid: &str, key: &str) -> String {
-        let params = json!({"ssid":ssid,"key":key});
-        self.jsonrpc.build_request(SET_WIFI_NETWORK, Some(params))
+    pub(crate) fn clear(&self) -> Vec<u32> {
+        self.jsonrpc.clear()
     }
 
-    pub(crate) fn delete_wifi_network(&self, ssid: &str) -> String {
-        let params = json!({"ssid":ssid});
+    pub(crate) fn parse(&self, msg: &str) -> Vec<Message> {
         self.jsonrpc
-            .build_request(DELETE_WIFI_NETWORK, Some(params))
+            .parse(msg)
+            .into_iter()
+            .filter_map(Self::message)
+            .collect()
     }
 
-    pub(crate) fn get_file_list(&self, path: Option<&str>) -> String {
-        let params = path.map(|p| json!({"path":p}));
-        self.jsonrpc.build_request(GET_FILE_LIST, params)
+    fn message(msg: jsonrpc::Message) -> Option<Message> {
+        match msg {
+            jsonrpc::Message::Response { id, data } => Some(Message::Response(id, data)),
+            jsonrpc::Message::Invalid { id, error } => Some(Message::Invalid(id, error)),
+            jsonrpc::Message::Notification { method, data } => match method.as_str() {
+                TRACK_CHANGED => Some(Self::notification(&method, data, Notification::Track)),
+                VOLUME_CHANGED => Some(Self::notification(&method, data, Notification::Volume)),
+                STORAGE_CHANGED => Some(Self::notification(&method, data, Notification::Storage)),
+                _ => {
+                    warn!("Received unknown notification: {method}");
+                    None
+                }
+            },
+        }
     }
 
-    pub(crate) fn parse(&self, msg: &str) -> Option<Message> {
-        /*Response {
-            method: &'a str,
-            data: Result<Value, Error>,
-        },
-        Notification {
-            method: &'a str,
-            data: Value,
-        },*/
-
-        /*InfoCon(Result<InfoCon, Error>),
-        ScanResult(Result<Vec<Network>, Error>),
-        NetworkList(Result<Vec<String>, Error>),*/
-
-        if let Some(msg) = self.jsonrpc.parse(msg) {
-            match msg {
-                jsonrpc::Message::Response { method, data } => match method {
-                    GET_INFO_CONNECTION => match data {
-                        Ok(v) => match serde_json::from_value(v) {
-                            Ok(o) => Some(Message::Response(Response::InfoConnection(Ok(o)))),
-                            Err(e) => {
-                                error!("Could not parse response: {e}");
-                                None
-                            }
-                        },
-                        Err(e) => Some(Message::Response(Response::InfoConnection(Err(e)))),
-                    },
-                    GET_INFO_ABOUT => match data {
-                        Ok(v) => match serde_json::from_value(v) {
-                            Ok(o) => Some(Message::Response(Response::InfoAbout(Ok(o)))),
-                            Err(e) => {
-                                error!("Could not parse response: {e}");
-                                None
-                            }
-                        },
-                        Err(e) => Some(Message::Response(Response::InfoAbout(Err(e)))),
-                    },
-                    GET_INFO_MEMORY => match data {
-                        Ok(v) => match serde_json::from_value(v) {
-                            Ok(o) => Some(Message::Response(Response::InfoMemory(Ok(o)))),
-                            Err(e) => {
-                                error!("Could not parse response: {e}");
-                                None
-                            }
-                        },
-                        Err(e) => Some(Message::Response(Response::InfoMemory(Err(e)))),
-                    },
-                    GET_INFO_SPIFLASH => match data {
-                        Ok(v) => match serde_json::from_value(v) {
-                            Ok(o) => Some(Message::Response(Response::InfoSPIFlash(Ok(o)))),
-                            Err(e) => {
-                                error!("Could not parse response: {e}");
-                                None
-                            }
-                        },
-                        Err(e) => Some(Message::Response(Response::InfoSPIFlash(Err(e)))),
-                    },
-                    GET_WIFI_SCAN_RESULT => match data {
-                        Ok(v) => match serde_json::from_value(v) {
-                            Ok(o) => Some(Message::Response(Response::ScanResult(Ok(o)))),
-                            Err(e) => {
-                                error!("Could not parse response: {e}");
-                                None
-                            }
-                        },
-                        Err(e) => Some(Message::Response(Response::ScanResult(Err(e)))),
-                    },
-                    GET_WIFI_NETWORK_LIST => match data {
-                        Ok(v) => match serde_json::from_value(v) {
-                            Ok(o) => Some(Message::Response(Response::NetworkList(Ok(o)))),
-                            Err(e) => {
-                                error!("Could not parse response: {e}");
-                                None
-                            }
-                        },
-                        Err(e) => Some(Message::Response(Response::NetworkList(Err(e)))),
-                    },
-                    SET_WIFI_NETWORK => match data {
-                        Ok(v) => match serde_json::from_value(v) {
-                            Ok(o) => Some(Message::Response(Response::SetNetwork(Ok(o)))),
-                            Err(e) => {
-                                error!("Could not parse response: {e}");
-                                None
-                            }
-                        },
-                        Err(e) => Some(Message::Response(Response::SetNetwork(Err(e)))),
-                    },
-                    DELETE_WIFI_NETWORK => match data {
-                        Ok(v) => match serde_json::from_value(v) {
-                            Ok(o) => Some(Message::Response(Response::DeleteNetwork(Ok(o)))),
-                            Err(e) => {
-                                error!("Could not parse response: {e}");
-                                None
-                            }
-                        },
-                        Err(e) => Some(Message::Response(Response::DeleteNetwork(Err(e)))),
-                    },
-                    GET_FILE_LIST => match data {
-                        Ok(v) => match serde_json::from_value(v) {
-                            Ok(o) => Some(Message::Response(Response::FileList(Ok(o)))),
-                            Err(e) => {
-                                error!("Could not parse response: {e}");
-                                None
-                            }
-                        },
-                        Err(e) => Some(Message::Response(Response::FileList(Err(e)))),
-                    },
-                    _ => {
-                        error!("Received response with unknown method: {method}");
-                        None
-                    }
-                },
+    fn notification<T: DeserializeOwned>(
+        method: &str,
+        data: Value,
+        notification: fn(T) -> Notification,
+    ) -> Message {
+        match serde_json::from_value(data) {
+            Ok(o) => Message::Notification(notification(o)),
+            Err(e) => {
+                let error = format!("Could not parse {method} notification: {e}");
+                error!("{error}");
+                Message::Invalid(None, error)
             }
-        } else {
-            None
         }
     }
 }
 
-/*use serde::{Deserialize, Serialize};
+impl From<Connection> for crate::Connection {
+    fn from(connection: Connection) -> Self {
+        Self {
+            mode: connection.mode,
+        }
+    }
+}
+
+impl From<About> for crate::About {
+    fn from(about: About) -> Self {
+        Self {
+            project: about.project,
+            version: about.version,
+            esp_idf: about.esp_idf,
+        }
+    }
+}
+
+impl From<Memory> for crate::Memory {
+    fn from(memory: Memory) -> Self {
+        Self {
+            heap: crate::Heap {
+                allocated: memory.heap.allocated,
+                free: memory.heap.free,
+                minimum_free: memory.heap.minimum_free,
+            },
+        }
+    }
+}
+
+impl From<SPIFlash> for crate::SPIFlash {
+    fn from(flash: SPIFlash) -> Self {
+        Self {
+            total: flash.total,
+            free: flash.free,
+            files: flash
+                .files
+                .into_iter()
+                .map(|f| crate::File {
+                    name: f.name,
+                    content_type: f.content_type,
+                    size: f.size,
+                    md5: f.md5,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<FileEntry> for ListedFile {
+    fn from(entry: FileEntry) -> Self {
+        match entry {
+            FileEntry::Name(name) => Self {
+                name,
+                size: None,
+                mtime: None,
+                md5: None,
+            },
+            FileEntry::File(file) => file,
+        }
+    }
+}
+
+impl From<ScannedNetwork> for crate::Network {
+    fn from(network: ScannedNetwork) -> Self {
+        Self {
+            ssid: network.ssid,
+            rssi: network.rssi,
+        }
+    }
+}
 
-//pub(crate) const GET_FILE_LIST: &str = "get-file-list";
-//pub(crate) const GET_FILE_INFO: &str = "get-file-info";
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_notifications() {
+        let handler = Handler::default();
+        let msg = r#"[
+            {"jsonrpc":"2.0","method":"track-changed","params":{"file":null}},
+            {"jsonrpc":"2.0","method":"volume-changed","params":{"volume":7}},
+            {"jsonrpc":"2.0","method":"storage-changed","params":{"total":10,"free":4}}
+        ]"#;
+        assert!(matches!(
+            &handler.parse(msg)[..],
+            [
+                Message::Notification(Notification::Track(Track { file: None })),
+                Message::Notification(Notification::Volume(Volume { volume: 7 })),
+                Message::Notification(Notification::Storage(Storage { total: 10, free: 4 })),
+            ]
+        ));
+    }
+
+    #[test]
+    fn unknown_or_invalid_notifications() {
+        let handler = Handler::default();
+        let unknown = r#"{"jsonrpc":"2.0","method":"reboot","params":{}}"#;
+        assert!(handler.parse(unknown).is_empty());
+        let invalid = r#"{"jsonrpc":"2.0","method":"volume-changed","params":{"volume":"up"}}"#;
+        assert!(matches!(
+            &handler.parse(invalid)[..],
+            [Message::Invalid(None, _)]
+        ));
+    }
 
+    #[test]
+    fn params() {
+        assert_eq!(GetInfoAbout::params(&()), None);
+        let params = SetNetworkParams {
+            ssid: "net".to_owned(),
+            key: "key".to_owned(),
+        };
+        let value = SetWifiNetwork::params(&params).unwrap();
+        assert_eq!(value, serde_json::json!({"ssid": "net", "key": "key"}));
+    }
 
-//#[derive(Serialize)]
-//pub(crate) struct ParamGetFileList {
-//    pub start: bool,
-//}
+    #[test]
+    fn result_round_trip() {
+        let result = serde_json::json!([{"ssid": "net", "rssi": -40}]);
+        let evt = GetWifiScanResult::parse(Ok(result)).unwrap();
+        assert_eq!(evt.kind(), crate::EventKind::ScanResult);
+        let networks = GetWifiScanResult::extract(&evt).unwrap().unwrap();
+        assert_eq!(networks[0].ssid, "net");
+        assert_eq!(networks[0].rssi, -40);
+        // the event of another method is not taken for this one
+        assert!(GetWifiNetworkList::extract(&evt).is_none());
+    }
 
-//#[derive(Serialize)]
-//pub(crate) struct ParamGetFileInfo {
-//    pub filename: String,
-//}
+    #[test]
+    fn remote_error() {
+        let error = ExecError {
+            code: -32000,
+            message: "busy".to_owned(),
+        };
+        let evt = DeleteWifiNetwork::parse(Err(error)).unwrap();
+        assert!(matches!(
+            evt,
+            Event::DeleteNetwork(Err(RemoteError {
+                code: crate::ErrorCode::ServerError(-32000),
+                ..
+            }))
+        ));
+    }
 
-#[derive(Serialize)]
-#[serde(untagged)]
-pub(crate) enum Params {
-    //FileList(ParamGetFileList),
-    //FileInfo(ParamGetFileInfo),
-}
-
-//#[derive(Deserialize)]
-//pub(crate) struct FileList {
-//    pub first: bool,
-//    pub last: bool,
-//    pub files: Vec<String>,
-//}
-
-//#[derive(Deserialize)]
-//pub(crate) struct FileInfo {
-//    pub filename: String,
-//    pub genre: String,
-//    pub artist: String,
-//    pub album: String,
-//    pub title: String,
-//    pub date: Option<u16>,
-//    pub track: u16,
-//    pub duration: u16,
-//}
-
-*/
-
-/*pub(crate) fn get_file_list(&self, start: bool) -> String {
-    let rpc = self.request(
-        types::GET_FILE_LIST,
-        Some(Params::FileList(ParamGetFileList { start })),
-    );
-    serde_json::to_string(&rpc).unwrap()
-}
-
-pub(crate) fn get_file_info(&self, filename: String) -> String {
-    let rpc = self.request(
-        types::GET_FILE_INFO,
-        Some(Params::FileInfo(ParamGetFileInfo { filename })),
-    );
-    serde_json::to_string(&rpc).unwrap()
-}*/
+    #[test]
+    fn unexpected_result() {
+        let result = serde_json::json!({"heap": "full"});
+        assert!(GetInfoMemory::parse(Ok(result)).is_err());
+    }
+}
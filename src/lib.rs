@@ -1,65 +1,250 @@
+//! # Features
+//!
+//! - `async`: a [`RequestHandle`] can be awaited and `Backend::events` returns a `Stream` of
+//!   events, both work with any async runtime.
+//! - `serde`: the model types implement `Serialize` and `Deserialize`. Fields and enum variants
+//!   are kebab-case like in the device protocol, e.g. `{"project": "audio", "version": "1.2",
+//!   "esp-idf": "v5.1"}` for [`About`]. Enums are externally tagged, e.g. `"idle"` or
+//!   `{"done": 12}` for a [`SyncStatus`], and an [`ErrorCode`] is its number. Durations are
+//!   seconds, e.g. `{"reconnecting": {"attempt": 2, "next-in": 1.5}}` for a
+//!   [`ConnectionState`].
+
 mod com;
 mod common;
+mod config;
+mod database;
+mod file_sync;
+#[cfg(feature = "async")]
+mod future;
 mod json;
+mod subscription;
 
 use std::cell::Cell;
+use std::collections::HashMap;
 use std::fmt;
-use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{Arc, Condvar, Mutex};
+use std::net::SocketAddr;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex, PoisonError};
 use std::sync::{MutexGuard, mpsc};
 use std::thread::{Builder, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+use log::{debug, error, info, warn};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-use log::{debug, error, info};
+use crate::common::access_point::{self, Connector};
+use crate::common::{oneshot, worker};
+use crate::file_sync::{FileSync, Request};
+use crate::json::{Handler, Message, Notification, Parser, RpcEvent, RpcMethod};
+use crate::subscription::Subscribers;
 
-use crate::common::access_point::Connector;
-use crate::json::{Handler, Message, Response};
+pub use crate::config::{BackendConfig, Discovery};
+pub use crate::database::{Database, DirEntry};
+#[cfg(feature = "async")]
+pub use crate::future::EventStream;
+pub use crate::subscription::Subscription;
 
 pub const NAME: &str = env!("CARGO_PKG_NAME");
 pub const VERSION: &str = env!("VERSION");
+pub const PROTOCOL_VERSION: u16 = 1;
 
 pub struct Backend {
     handle: Option<JoinHandle<()>>,
     cmd_sender: Sender<Command>,
-    evt_sender: Sender<Event>,
-    receiver: Cell<Option<Receiver<Event>>>,
-    shared: Arc<(Mutex<SharedData>, Condvar)>,
-    //database: Database,
+    events: Arc<Subscribers>,
+    // receiver() has been called
+    receiver_taken: Cell<bool>,
+    shared: Arc<Mutex<SharedData>>,
+    database: Database,
 }
 
-pub enum Event {
-    Connected,
-    Disconnected,
-    InfoConnection(Result<Connection, RemoteError>),
-    InfoAbout(Result<About, RemoteError>),
-    InfoMemory(Result<Memory, RemoteError>),
-    InfoSPIFlash(Result<SPIFlash, RemoteError>),
-    ScanResult(Result<Vec<Network>, RemoteError>),
-    NetworkList(Result<Vec<String>, RemoteError>),
-    SetNetwork(Result<(), RemoteError>),
-    DeleteNetwork(Result<(), RemoteError>),
-    FileSyncStatus,
-    //Reload(Reload),
+// the events of the rpc methods are generated from their declaration in json.rs
+macro_rules! events {
+    ($($name:ident($method:literal, $params:ty) -> $result:ty => $event:ident($output:ty) = $convert:expr;)*) => {
+        #[derive(Debug, Clone)]
+        pub enum Event {
+            Connected,
+            Disconnected,
+            ConnectionState(ConnectionState),
+            DeviceFound(DiscoveredDevice),
+            DeviceLost(String),
+            $($event(Result<$output, RemoteError>),)*
+            FileSyncStatus,
+            /// A track whose info the device could not read during a file sync, it is kept
+            /// without its tags.
+            FileInfoError(String, RemoteError),
+            /// Path of the track being played, `None` if playback stopped.
+            TrackChanged(Option<String>),
+            VolumeChanged(u8),
+            StorageChanged(Storage),
+            /// A message from the device that could not be understood.
+            ProtocolError(String),
+            /// A worker of the backend failed, e.g. the mDNS daemon without multicast. It is
+            /// restarted if [`BackendConfig::restart_failed`] is set, otherwise it stays down.
+            Fatal(String),
+        }
+
+        /// The kind of an [`Event`], used to filter subscriptions.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum EventKind {
+            Connected,
+            Disconnected,
+            ConnectionState,
+            DeviceFound,
+            DeviceLost,
+            $($event,)*
+            FileSyncStatus,
+            FileInfoError,
+            TrackChanged,
+            VolumeChanged,
+            StorageChanged,
+            ProtocolError,
+            Fatal,
+        }
+
+        impl Event {
+            pub fn kind(&self) -> EventKind {
+                match self {
+                    Self::Connected => EventKind::Connected,
+                    Self::Disconnected => EventKind::Disconnected,
+                    Self::ConnectionState(_) => EventKind::ConnectionState,
+                    Self::DeviceFound(_) => EventKind::DeviceFound,
+                    Self::DeviceLost(_) => EventKind::DeviceLost,
+                    $(Self::$event(_) => EventKind::$event,)*
+                    Self::FileSyncStatus => EventKind::FileSyncStatus,
+                    Self::FileInfoError(..) => EventKind::FileInfoError,
+                    Self::TrackChanged(_) => EventKind::TrackChanged,
+                    Self::VolumeChanged(_) => EventKind::VolumeChanged,
+                    Self::StorageChanged(_) => EventKind::StorageChanged,
+                    Self::ProtocolError(_) => EventKind::ProtocolError,
+                    Self::Fatal(_) => EventKind::Fatal,
+                }
+            }
+        }
+    };
 }
 
+json::rpc_methods!(events);
+
 #[derive(Debug)]
 pub enum Error {
     NotConnected,
     AlreadyRunning,
+    UnknownDevice,
+    IncompatibleDevice,
+    Remote(RemoteError),
+    Timeout,
+    Disconnected,
+    Cancelled,
+    InvalidResponse(String),
+    Config(String),
+    BackendDead,
+}
+
+/// Handle to a request sent to the device, resolving to the typed result of that request.
+///
+/// With the `async` feature it is also a [`Future`] of that result.
+pub struct RequestHandle<T> {
+    receiver: oneshot::Receiver<Result<T, Error>>,
+    done: Cell<bool>,
+}
+
+/// Requests with their own timeout, returned by [`Backend::with_timeout`].
+pub struct WithTimeout<'a> {
+    backend: &'a Backend,
+    timeout: Duration,
+}
+
+/// Handles to the requests of [`Backend::refresh_all`].
+pub struct Refresh {
+    pub connection: RequestHandle<Connection>,
+    pub about: RequestHandle<About>,
+    pub memory: RequestHandle<Memory>,
+    pub spiflash: RequestHandle<SPIFlash>,
+    pub networks: RequestHandle<Vec<String>>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", rename_all_fields = "kebab-case")
+)]
+pub enum ConnectionState {
+    #[default]
+    Discovering,
+    Connecting,
+    Connected,
+    Reconnecting {
+        attempt: u32,
+        #[cfg_attr(feature = "serde", serde(with = "secs"))]
+        next_in: Duration,
+    },
+    Failed,
+}
+
+/// A device found by mDNS, identified by its `fullname`.
+///
+/// `name`, `firmware_version`, `protocol_version` and `capabilities` are taken from the TXT
+/// properties `name`, `fw`, `protocol` and `caps` (comma separated), all of them are optional.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub struct DiscoveredDevice {
+    pub fullname: String,
+    pub hostname: String,
+    pub addresses: Vec<SocketAddr>,
+    pub interfaces: Vec<String>,
+    pub name: Option<String>,
+    pub firmware_version: Option<String>,
+    pub protocol_version: Option<u16>,
+    pub capabilities: Vec<String>,
+    pub properties: HashMap<String, String>,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub struct RemoteError {
-    pub code: i16,
+    pub code: ErrorCode,
     pub message: String,
 }
 
+/// Error code of a [`RemoteError`].
+///
+/// Besides the standard JSON-RPC codes the device may use the range reserved for
+/// implementation-defined server errors, -32099 to -32000. Any other code is kept as `Other`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "i16", into = "i16"))]
+pub enum ErrorCode {
+    /// -32700
+    ParseError,
+    /// -32600
+    InvalidRequest,
+    /// -32601
+    MethodNotFound,
+    /// -32602
+    InvalidParams,
+    /// -32603
+    InternalError,
+    /// -32099 to -32000
+    ServerError(i16),
+    Other(i16),
+}
+
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub struct Connection {
     pub mode: String,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub struct About {
     pub project: String,
     pub version: String,
@@ -67,11 +252,15 @@ pub struct About {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub struct Memory {
     pub heap: Heap,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub struct Heap {
     pub allocated: u32,
     pub free: u32,
@@ -79,6 +268,8 @@ pub struct Heap {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub struct SPIFlash {
     pub total: u32,
     pub free: u32,
@@ -86,6 +277,16 @@ pub struct SPIFlash {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub struct Storage {
+    pub total: u32,
+    pub free: u32,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub struct File {
     pub name: String,
     pub content_type: String,
@@ -94,12 +295,26 @@ pub struct File {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub struct Network {
     pub ssid: String,
     pub rssi: i8,
 }
 
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub struct Status {
+    pub access_point_mode: bool,
+    pub connection_state: ConnectionState,
+    pub sync_status: SyncStatus,
+    pub device: Option<DiscoveredDevice>,
+}
+
 #[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub enum SyncStatus {
     #[default]
     Idle,
@@ -108,31 +323,76 @@ pub enum SyncStatus {
     Aborted,
     Timeout,
     Disconnected,
+    InvalidResponse,
     Error(RemoteError),
 }
 
+type Reply = Box<dyn FnOnce(Result<&Event, Error>) + Send>;
+
 enum Command {
-    GetAccessPointMode,
     SetAccessPointMode(bool),
-    GetInfoConnection,
-    GetInfoAbout,
-    GetInfoMemory,
-    GetInfoSPIFlash,
-    GetWifiScanResult,
-    GetWifiNetworkList,
-    SetWifiNetwork { ssid: String, key: String },
-    DeleteWifiNetwork { ssid: String },
+    ConnectTo(SocketAddr),
+    SelectDevice(String),
+    Request {
+        method: &'static str,
+        params: Option<Value>,
+        // the request timeout of the handler if None
+        timeout: Option<Duration>,
+        parser: Parser,
+        reply: Reply,
+    },
+    RefreshAll(Vec<(&'static str, Parser, Reply)>),
     ResyncFiles,
+    SetRequestTimeout(Duration),
+    Com(com::Event),
+    AccessPoint(access_point::Event),
     Quit,
 }
 
+impl From<com::Event> for Command {
+    fn from(event: com::Event) -> Self {
+        Self::Com(event)
+    }
+}
+
+impl From<access_point::Event> for Command {
+    fn from(event: access_point::Event) -> Self {
+        Self::AccessPoint(event)
+    }
+}
+
+enum Pending {
+    Request { parser: Parser, reply: Reply },
+    Flash { walk: u32 },
+    FileList { walk: u32, dir: String },
+    FileInfo { walk: u32, filename: String },
+}
+
 #[derive(Default)]
 struct SharedData {
+    access_point_mode: bool,
     connected: bool,
-    ap_mode: bool,
+    connection_state: ConnectionState,
+    devices: Vec<DiscoveredDevice>,
+    device: Option<String>,
     sync_files: SyncStatus,
 }
 
+impl SharedData {
+    // the data stays usable after a panic of the backend thread
+    fn lock(shared: &Mutex<Self>) -> MutexGuard<'_, Self> {
+        shared.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn current_device(&self) -> Option<DiscoveredDevice> {
+        let fullname = self.device.as_ref()?;
+        self.devices
+            .iter()
+            .find(|d| &d.fullname == fullname)
+            .cloned()
+    }
+}
+
 //pub enum Reload {
 //    Start,
 //    Step(Option<(usize, usize)>),
@@ -141,440 +401,857 @@ struct SharedData {
 
 impl Backend {
     pub fn new() -> Self {
+        Self::with_config(BackendConfig::default())
+    }
+
+    pub fn with_config(config: BackendConfig) -> Self {
         let (cmd_sender, rx) = mpsc::channel();
-        let (tx, receiver) = mpsc::channel();
-        let evt_sender = tx.clone();
-        let receiver = Cell::new(Some(receiver));
-        let shared = Arc::new((Mutex::new(SharedData::default()), Condvar::new()));
+        let events = Arc::new(Subscribers::default());
+        let tx = events.clone();
+        let shared = Arc::new(Mutex::new(SharedData::default()));
         let shared_thread = shared.clone();
-        //let database = Database::new(sender.clone());
+        let database = Database::new(config.cache_dir.clone());
         let handle = {
-            //let database = database.clone();
-            Builder::new()
-                .name("audio:backend".into())
-                .spawn(move || Self::thread(tx, rx, shared_thread))
-                .unwrap()
+            let database = database.clone();
+            Builder::new().name("audio:backend".into()).spawn({
+                let cmd_sender = cmd_sender.clone();
+                move || Self::supervise(&tx, &cmd_sender, &rx, &shared_thread, &database, &config)
+            })
+        };
+        // without the thread every method returns BackendDead
+        let handle = match handle {
+            Ok(handle) => Some(handle),
+            Err(e) => {
+                let msg = format!("backend thread failed: {e}");
+                error!("{msg}");
+                events.send(Event::Fatal(msg));
+                None
+            }
         };
         Self {
-            handle: Some(handle),
+            handle,
             cmd_sender,
-            evt_sender,
-            receiver,
+            events,
+            receiver_taken: Cell::new(false),
             shared,
-            //database,
+            database,
         }
     }
 
+    /// A receiver for all events from now on. It can be taken only once, use
+    /// [`subscribe`](Self::subscribe) for more.
     pub fn receiver(&self) -> Option<Receiver<Event>> {
-        self.receiver.take()
+        // subscribed only now, so that unused events do not pile up
+        (!self.receiver_taken.replace(true)).then(|| self.subscribe(None))
     }
 
-    pub fn get_access_point_mode(&self) -> bool {
-        let (mutex, cvar) = &*self.shared;
-        let mut data = mutex.lock().unwrap();
-        self.cmd_sender.send(Command::GetAccessPointMode).unwrap();
-        data = cvar.wait(data).unwrap();
-        data.ap_mode
+    /// Returns a new receiver for the events of the given kinds, or all events if `None`.
+    /// It is unsubscribed when dropped.
+    pub fn subscribe(&self, kinds: Option<&[EventKind]>) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::channel();
+        self.events
+            .channel(kinds.map(<[EventKind]>::to_vec), sender);
+        receiver
     }
 
-    pub fn set_access_point_mode(&self, auto: bool) {
-        self.cmd_sender
-            .send(Command::SetAccessPointMode(auto))
-            .unwrap();
+    /// Calls `callback` for the events of the given kinds, or all events if `None`, until the
+    /// returned [`Subscription`] is dropped.
+    ///
+    /// The callback runs on the backend thread, so it should return quickly. It may call any
+    /// method of the backend. A callback that panics is removed, the backend carries on.
+    pub fn listen(
+        &self,
+        kinds: Option<&[EventKind]>,
+        callback: impl Fn(&Event) + Send + Sync + 'static,
+    ) -> Subscription {
+        self.events
+            .callback(kinds.map(<[EventKind]>::to_vec), Box::new(callback))
     }
 
-    pub fn get_info_connection(&self) -> Result<(), Error> {
-        let (mutex, _) = &*self.shared;
-        let data = mutex.lock().unwrap();
-        if !data.connected {
-            return Err(Error::NotConnected);
-        }
-        self.cmd_sender.send(Command::GetInfoConnection).unwrap();
-        Ok(())
+    /// Returns a stream of the events of the given kinds, or all events if `None`.
+    /// It is unsubscribed when dropped.
+    #[cfg(feature = "async")]
+    pub fn events(&self, kinds: Option<&[EventKind]>) -> EventStream {
+        EventStream::new(&self.events, kinds.map(<[EventKind]>::to_vec))
     }
 
-    pub fn get_info_about(&self) -> Result<(), Error> {
-        let (mutex, _) = &*self.shared;
-        let data = mutex.lock().unwrap();
-        if !data.connected {
-            return Err(Error::NotConnected);
-        }
-        self.cmd_sender.send(Command::GetInfoAbout).unwrap();
-        Ok(())
+    pub fn get_access_point_mode(&self) -> Result<bool, Error> {
+        Ok(self.running()?.access_point_mode)
     }
 
-    pub fn get_info_memory(&self) -> Result<(), Error> {
-        let (mutex, _) = &*self.shared;
-        let data = mutex.lock().unwrap();
-        if !data.connected {
-            return Err(Error::NotConnected);
-        }
-        self.cmd_sender.send(Command::GetInfoMemory).unwrap();
-        Ok(())
+    /// A consistent snapshot of the backend's state.
+    pub fn status(&self) -> Result<Status, Error> {
+        let data = self.running()?;
+        Ok(Status {
+            access_point_mode: data.access_point_mode,
+            connection_state: data.connection_state.clone(),
+            sync_status: data.sync_files.clone(),
+            device: data.current_device(),
+        })
     }
 
-    pub fn get_info_spiflash(&self) -> Result<(), Error> {
-        let (mutex, _) = &*self.shared;
-        let data = mutex.lock().unwrap();
-        if !data.connected {
-            return Err(Error::NotConnected);
-        }
-        self.cmd_sender.send(Command::GetInfoSPIFlash).unwrap();
-        Ok(())
+    pub fn set_access_point_mode(&self, auto: bool) -> Result<(), Error> {
+        self.send(Command::SetAccessPointMode(auto))
     }
 
-    pub fn get_wifi_scan_result(&self) -> Result<(), Error> {
-        let (mutex, _) = &*self.shared;
-        let data = mutex.lock().unwrap();
-        if !data.connected {
-            return Err(Error::NotConnected);
-        }
-        self.cmd_sender.send(Command::GetWifiScanResult).unwrap();
-        Ok(())
+    pub fn connect_to(&self, addr: SocketAddr) -> Result<(), Error> {
+        self.send(Command::ConnectTo(addr))
     }
 
-    pub fn get_wifi_network_list(&self) -> Result<(), Error> {
-        let (mutex, _) = &*self.shared;
-        let data = mutex.lock().unwrap();
-        if !data.connected {
-            return Err(Error::NotConnected);
-        }
-        self.cmd_sender.send(Command::GetWifiNetworkList).unwrap();
-        Ok(())
+    pub fn connection_state(&self) -> ConnectionState {
+        let data = SharedData::lock(&self.shared);
+        data.connection_state.clone()
     }
 
-    pub fn set_wifi_network(&self, ssid: String, key: String) -> Result<(), Error> {
-        let (mutex, _) = &*self.shared;
-        let data = mutex.lock().unwrap();
-        if !data.connected {
-            return Err(Error::NotConnected);
+    pub fn devices(&self) -> Vec<DiscoveredDevice> {
+        let data = SharedData::lock(&self.shared);
+        data.devices.clone()
+    }
+
+    /// The discovered device the backend is connected to, `None` if it is not connected or
+    /// connected to a static endpoint.
+    pub fn current_device(&self) -> Option<DiscoveredDevice> {
+        let data = SharedData::lock(&self.shared);
+        data.current_device()
+    }
+
+    pub fn select_device(&self, fullname: &str) -> Result<(), Error> {
+        let data = SharedData::lock(&self.shared);
+        match data.devices.iter().find(|d| d.fullname == fullname) {
+            None => return Err(Error::UnknownDevice),
+            Some(d) if !d.is_compatible() => return Err(Error::IncompatibleDevice),
+            Some(_) => {}
         }
-        self.cmd_sender
-            .send(Command::SetWifiNetwork { ssid, key })
-            .unwrap();
-        Ok(())
+        self.send(Command::SelectDevice(fullname.to_owned()))
     }
 
-    pub fn delete_wifi_network(&self, ssid: String) -> Result<(), Error> {
-        let (mutex, _) = &*self.shared;
-        let data = mutex.lock().unwrap();
-        if !data.connected {
-            return Err(Error::NotConnected);
+    /// Sends the following requests with `timeout` instead of
+    /// [`BackendConfig::request_timeout`], e.g. for a Wi-Fi scan that takes longer.
+    pub fn with_timeout(&self, timeout: Duration) -> WithTimeout<'_> {
+        WithTimeout {
+            backend: self,
+            timeout,
         }
-        self.cmd_sender
-            .send(Command::DeleteWifiNetwork { ssid })
-            .unwrap();
-        Ok(())
+    }
+
+    pub fn get_info_connection(&self) -> Result<RequestHandle<Connection>, Error> {
+        self.request::<json::GetInfoConnection>(&(), None)
+    }
+
+    pub fn get_info_about(&self) -> Result<RequestHandle<About>, Error> {
+        self.request::<json::GetInfoAbout>(&(), None)
+    }
+
+    pub fn get_info_memory(&self) -> Result<RequestHandle<Memory>, Error> {
+        self.request::<json::GetInfoMemory>(&(), None)
+    }
+
+    pub fn get_info_spiflash(&self) -> Result<RequestHandle<SPIFlash>, Error> {
+        self.request::<json::GetInfoSPIFlash>(&(), None)
+    }
+
+    pub fn get_wifi_scan_result(&self) -> Result<RequestHandle<Vec<Network>>, Error> {
+        self.request::<json::GetWifiScanResult>(&(), None)
+    }
+
+    pub fn get_wifi_network_list(&self) -> Result<RequestHandle<Vec<String>>, Error> {
+        self.request::<json::GetWifiNetworkList>(&(), None)
+    }
+
+    pub fn set_wifi_network(&self, ssid: String, key: String) -> Result<RequestHandle<()>, Error> {
+        self.request::<json::SetWifiNetwork>(&json::SetNetworkParams { ssid, key }, None)
+    }
+
+    pub fn delete_wifi_network(&self, ssid: String) -> Result<RequestHandle<()>, Error> {
+        self.request::<json::DeleteWifiNetwork>(&json::DeleteNetworkParams { ssid }, None)
+    }
+
+    /// Requests connection, about, memory, SPI flash and the stored networks in one batch.
+    ///
+    /// The results are reported as events as well as through the returned handles, which also
+    /// resolve if a request of the batch fails.
+    pub fn refresh_all(&self) -> Result<Refresh, Error> {
+        let _data = self.connected()?;
+        let (connection, reply_connection) = Self::handle::<json::GetInfoConnection>();
+        let (about, reply_about) = Self::handle::<json::GetInfoAbout>();
+        let (memory, reply_memory) = Self::handle::<json::GetInfoMemory>();
+        let (spiflash, reply_spiflash) = Self::handle::<json::GetInfoSPIFlash>();
+        let (networks, reply_networks) = Self::handle::<json::GetWifiNetworkList>();
+        self.send(Command::RefreshAll(vec![
+            reply_connection,
+            reply_about,
+            reply_memory,
+            reply_spiflash,
+            reply_networks,
+        ]))?;
+        Ok(Refresh {
+            connection,
+            about,
+            memory,
+            spiflash,
+            networks,
+        })
     }
 
     pub fn sync_files_start(&self) -> Result<(), Error> {
-        let (mutex, _) = &*self.shared;
-        let mut data = mutex.lock().unwrap();
-        if !data.connected {
-            return Err(Error::NotConnected);
-        }
+        let mut data = self.connected()?;
         if let SyncStatus::Running = data.sync_files {
             return Err(Error::AlreadyRunning);
         }
+        // the lock is held, so the backend thread cannot finish before the status is set, it
+        // also reports the new status
+        self.send(Command::ResyncFiles)?;
         data.sync_files = SyncStatus::Running;
-        self.evt_sender.send(Event::FileSyncStatus).unwrap();
-        self.cmd_sender.send(Command::ResyncFiles).unwrap();
         Ok(())
     }
 
     pub fn sync_files_status(&self) -> SyncStatus {
-        let (mutex, _) = &*self.shared;
-        let data = mutex.lock().unwrap();
+        let data = SharedData::lock(&self.shared);
         data.sync_files.clone()
     }
 
-    //pub fn database(&self) -> Database {
-    //    self.database.clone()
-    //}
+    pub fn set_request_timeout(&self, timeout: Duration) -> Result<(), Error> {
+        self.send(Command::SetRequestTimeout(timeout))
+    }
+
+    fn send(&self, command: Command) -> Result<(), Error> {
+        self.cmd_sender
+            .send(command)
+            .map_err(|_| Error::BackendDead)
+    }
+
+    // returns the locked data if the backend thread is running
+    fn running(&self) -> Result<MutexGuard<'_, SharedData>, Error> {
+        if self.handle.as_ref().is_none_or(JoinHandle::is_finished) {
+            return Err(Error::BackendDead);
+        }
+        Ok(SharedData::lock(&self.shared))
+    }
+
+    // returns the locked data if the backend is connected to a device
+    fn connected(&self) -> Result<MutexGuard<'_, SharedData>, Error> {
+        let data = self.running()?;
+        if !data.connected {
+            return Err(Error::NotConnected);
+        }
+        Ok(data)
+    }
+
+    fn request<M: RpcEvent>(
+        &self,
+        params: &M::Params,
+        timeout: Option<Duration>,
+    ) -> Result<RequestHandle<M::Output>, Error> {
+        let _data = self.connected()?;
+        let (handle, (method, parser, reply)) = Self::handle::<M>();
+        self.send(Command::Request {
+            method,
+            params: M::params(params),
+            timeout,
+            parser,
+            reply,
+        })?;
+        Ok(handle)
+    }
+
+    // a handle with the reply that resolves it from the event of the response
+    fn handle<M: RpcEvent>() -> (RequestHandle<M::Output>, (&'static str, Parser, Reply)) {
+        let (sender, receiver) = oneshot::channel();
+        let reply: Reply = Box::new(move |res| {
+            let res = match res {
+                Ok(evt) => match M::extract(evt) {
+                    Some(res) => res.map_err(Error::Remote),
+                    None => return,
+                },
+                Err(e) => Err(e),
+            };
+            sender.send(res);
+        });
+        let handle = RequestHandle {
+            receiver,
+            done: Cell::new(false),
+        };
+        (handle, (M::NAME, M::parse, reply))
+    }
+
+    /// The tracks found by the last file sync, see [`sync_files_start`](Self::sync_files_start).
+    pub fn database(&self) -> Database {
+        self.database.clone()
+    }
+
+    // runs the backend thread, which is restarted after a panic if restarts are enabled
+    fn supervise(
+        tx: &Subscribers,
+        own: &Sender<Command>,
+        rx: &Receiver<Command>,
+        shared: &Mutex<SharedData>,
+        database: &Database,
+        config: &BackendConfig,
+    ) {
+        loop {
+            let run = || Self::thread(tx, own, rx, shared, database, config);
+            let Err(msg) = worker::run("audio:backend", run) else {
+                break;
+            };
+            // the connection and the discovered devices are gone with the com of the thread
+            let data = std::mem::take(&mut *SharedData::lock(shared));
+            if data.connected {
+                tx.send(Event::Disconnected);
+            }
+            if let SyncStatus::Running = data.sync_files {
+                Self::sync_status(SyncStatus::Aborted, shared, tx);
+            }
+            tx.send(Event::Fatal(msg));
+            let Some(delay) = config.restart_delay else {
+                break;
+            };
+            // commands until the restart are dropped, their callers get BackendDead
+            let restart = Instant::now() + delay;
+            loop {
+                match rx.recv_timeout(restart.saturating_duration_since(Instant::now())) {
+                    Ok(Command::Quit) | Err(RecvTimeoutError::Disconnected) => return,
+                    Ok(_) => {}
+                    Err(RecvTimeoutError::Timeout) => break,
+                }
+            }
+            info!("restarting backend thread");
+        }
+    }
 
-    //fn thread(tx: Sender<Event>, rx: Receiver<Command>, database: Database) {
-    fn thread(tx: Sender<Event>, rx: Receiver<Command>, shared: Arc<(Mutex<SharedData>, Condvar)>) {
-        let com = com::Com::new();
+    fn thread(
+        tx: &Subscribers,
+        own: &Sender<Command>,
+        rx: &Receiver<Command>,
+        shared: &Mutex<SharedData>,
+        database: &Database,
+        config: &BackendConfig,
+    ) {
+        let mut com = com::Com::new(config.clone(), own.clone());
         let json = Handler::default();
-        let (mutex, cvar) = &*shared;
+        json.set_timeout(config.request_timeout);
         let mut ap = None;
+        let mut pending = HashMap::new();
+        let mut sync = FileSync::new(database.clone());
+        let mut com_restart = None;
+        let mut ap_restart = None;
 
         loop {
-            if let Ok(cmd) = rx.try_recv() {
-                match cmd {
-                    Command::GetAccessPointMode => {
-                        let mut data = mutex.lock().unwrap();
-                        data.ap_mode = ap.is_some();
-                        cvar.notify_one();
-                    }
+            // sleep until the next command or com event, or until the next request expires or
+            // a failed worker is restarted
+            let deadline = [json.next_deadline(), com_restart, ap_restart]
+                .into_iter()
+                .flatten()
+                .min();
+            let cmd = match deadline {
+                Some(t) => rx.recv_timeout(t.saturating_duration_since(Instant::now())),
+                None => rx.recv().map_err(RecvTimeoutError::from),
+            };
+            match cmd {
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+                Ok(cmd) => match cmd {
                     Command::SetAccessPointMode(auto) => {
+                        ap_restart = None;
                         if auto && ap.is_none() {
-                            ap = Some(Connector::new(
-                                "esp32-audio".to_owned(),
-                                "secret-wifi-key".to_owned(),
-                            ));
+                            ap = Self::access_point(config, own, tx, &mut ap_restart);
                         } else if !auto && ap.is_some() {
                             ap.take();
                         }
                     }
-                    Command::GetInfoConnection => {
-                        com.send(json.get_info_connection());
-                    }
-                    Command::GetInfoAbout => {
-                        com.send(json.get_info_about());
-                    }
-                    Command::GetInfoMemory => {
-                        com.send(json.get_info_memory());
+                    Command::ConnectTo(addr) => {
+                        com.connect(addr);
                     }
-                    Command::GetInfoSPIFlash => {
-                        com.send(json.get_info_spiflash());
+                    Command::SelectDevice(fullname) => {
+                        com.select(fullname);
                     }
-                    Command::GetWifiScanResult => {
-                        com.send(json.get_wifi_scan_result());
+                    Command::Request {
+                        method,
+                        params,
+                        timeout,
+                        parser,
+                        reply,
+                    } => {
+                        let (id, msg) = json.build_request(method, params, timeout);
+                        pending.insert(id, Pending::Request { parser, reply });
+                        com.send(msg);
                     }
-                    Command::GetWifiNetworkList => {
-                        com.send(json.get_wifi_network_list());
+                    Command::RefreshAll(calls) => {
+                        let (ids, msg) =
+                            json.build_batch(calls.iter().map(|(method, ..)| *method).collect());
+                        for (id, (_, parser, reply)) in ids.into_iter().zip(calls) {
+                            pending.insert(id, Pending::Request { parser, reply });
+                        }
+                        com.send(msg);
                     }
-                    Command::SetWifiNetwork { ssid, key } => {
-                        com.send(json.set_wifi_network(&ssid, &key));
+                    Command::ResyncFiles => {
+                        tx.send(Event::FileSyncStatus);
+                        sync.start();
+                        Self::sync_requests(&mut sync, &com, &json, &mut pending);
                     }
-                    Command::DeleteWifiNetwork { ssid } => {
-                        com.send(json.delete_wifi_network(&ssid));
+                    Command::SetRequestTimeout(timeout) => {
+                        json.set_timeout(timeout);
                     }
-                    Command::ResyncFiles => {
-                        com.send(json.get_file_list(None));
+                    Command::AccessPoint(access_point::Event::Failed(msg)) => {
+                        ap = None;
+                        worker::failed(msg, &mut ap_restart, config.restart_delay, |msg| {
+                            tx.send(Event::Fatal(msg));
+                        });
                     }
-                    /*Ok(Command::Resync) => {
-                        tx.send(Event::Reload(Reload::Start)).unwrap();
-                        com.send(rpc.get_file_list(true));
-                    }*/
+                    Command::Com(event) => match event {
+                        com::Event::Device { fullname, addr } => {
+                            // a device without a name is identified by its address
+                            let key = fullname.clone().unwrap_or_else(|| addr.to_string());
+                            database.select_device(key);
+                            let mut data = SharedData::lock(shared);
+                            data.device = fullname;
+                        }
+                        com::Event::Connected(_) => {
+                            info!("Connected!");
+                            SharedData::lock(shared).connected = true;
+                            tx.send(Event::Connected);
+                        }
+                        com::Event::Disconnected => {
+                            info!("Disconnected!");
+                            Self::disconnected(&json, &mut pending, &mut sync, tx, shared);
+                        }
+                        com::Event::State(state) => {
+                            SharedData::lock(shared).connection_state = state.clone();
+                            tx.send(Event::ConnectionState(state));
+                        }
+                        com::Event::DeviceFound(device) => {
+                            {
+                                let mut data = SharedData::lock(shared);
+                                if let Some(d) = data
+                                    .devices
+                                    .iter_mut()
+                                    .find(|d| d.fullname == device.fullname)
+                                {
+                                    *d = device.clone();
+                                } else {
+                                    data.devices.push(device.clone());
+                                }
+                            }
+                            tx.send(Event::DeviceFound(device));
+                        }
+                        com::Event::DeviceLost(fullname) => {
+                            SharedData::lock(shared)
+                                .devices
+                                .retain(|d| d.fullname != fullname);
+                            tx.send(Event::DeviceLost(fullname));
+                        }
+                        com::Event::Failed(msg) => {
+                            tx.send(Event::Fatal(msg));
+                        }
+                        com::Event::Fatal(msg) => {
+                            let connected = SharedData::lock(shared).connected;
+                            if connected {
+                                Self::disconnected(&json, &mut pending, &mut sync, tx, shared);
+                            }
+                            worker::failed(msg, &mut com_restart, config.restart_delay, |msg| {
+                                tx.send(Event::Fatal(msg));
+                            });
+                        }
+                        com::Event::Message(msg) => {
+                            debug!("Message: {msg}");
+                            for m in json.parse(&msg) {
+                                debug!("Backend received valid message :-)");
+                                Self::handle_message(
+                                    m,
+                                    &com,
+                                    &json,
+                                    tx,
+                                    shared,
+                                    &mut pending,
+                                    &mut sync,
+                                );
+                            }
+                        }
+                    },
                     Command::Quit => {
                         debug!("quit received");
                         break;
                     }
-                }
+                },
             }
 
-            if let Ok(event) = com.recv_timeout(Duration::from_millis(10)) {
-                match event {
-                    com::Event::Connected => {
-                        info!("Connected!");
-                        let mut data = mutex.lock().unwrap();
-                        data.connected = true;
-                        tx.send(Event::Connected).unwrap();
-                    }
-                    com::Event::Disconnected => {
-                        info!("Disconnected!");
-                        let mut data = mutex.lock().unwrap();
-                        data.connected = false;
-                        tx.send(Event::Disconnected).unwrap();
-                    }
-                    com::Event::Message(msg) => {
-                        debug!("Message: {msg}");
-                        if let Some(m) = json.parse(&msg) {
-                            debug!("Backend received valid message :-)");
-                            //Self::handle_message(m, &com, &mut rpc, &tx, &database);
-                            let data = mutex.lock().unwrap();
-                            Self::handle_message(m, &com, &json, &tx, data);
-                        }
-                        //tx.send(Event::Connected).unwrap();
+            let now = Instant::now();
+            if com_restart.is_some_and(|t| now >= t) {
+                com_restart = None;
+                info!("restarting com");
+                com = com::Com::new(config.clone(), own.clone());
+            }
+            if ap_restart.is_some_and(|t| now >= t) {
+                ap_restart = None;
+                info!("restarting access point");
+                ap = Self::access_point(config, own, tx, &mut ap_restart);
+            }
+            // kept in the shared data, so that it can be read without asking this thread
+            SharedData::lock(shared).access_point_mode = ap.is_some();
+
+            for id in json.expire() {
+                match pending.remove(&id) {
+                    Some(Pending::Request { reply, .. }) => reply(Err(Error::Timeout)),
+                    Some(
+                        Pending::Flash { walk }
+                        | Pending::FileList { walk, .. }
+                        | Pending::FileInfo { walk, .. },
+                    ) if sync.abort(walk) => {
+                        Self::sync_status(SyncStatus::Timeout, shared, tx);
                     }
+                    _ => {}
                 }
             }
         }
         debug!("quit");
     }
 
+    fn access_point(
+        config: &BackendConfig,
+        own: &Sender<Command>,
+        tx: &Subscribers,
+        restart: &mut Option<Instant>,
+    ) -> Option<Connector> {
+        let connector = Connector::new(
+            config.ap_ssid.clone(),
+            config.ap_key.clone(),
+            config.scan_interval,
+            config.proxy_timeout,
+            own.clone(),
+        );
+        match connector {
+            Ok(connector) => Some(connector),
+            Err(e) => {
+                error!("{e}");
+                worker::failed(e, restart, config.restart_delay, |msg| {
+                    tx.send(Event::Fatal(msg));
+                });
+                None
+            }
+        }
+    }
+
+    // sends the next requests of a running file sync
+    fn sync_requests(
+        sync: &mut FileSync,
+        com: &com::Com,
+        json: &Handler,
+        pending: &mut HashMap<u32, Pending>,
+    ) {
+        while let Some((walk, request)) = sync.next() {
+            match request {
+                Request::Flash => {
+                    let (id, msg) = json.request::<json::GetInfoSPIFlash>(&());
+                    pending.insert(id, Pending::Flash { walk });
+                    com.send(msg);
+                }
+                Request::List(dir) => {
+                    let params =
+                        (!dir.is_empty()).then(|| json::FileListParams { path: dir.clone() });
+                    let (id, msg) = json.request::<json::GetFileList>(&params);
+                    pending.insert(id, Pending::FileList { walk, dir });
+                    com.send(msg);
+                }
+                Request::Info(filename) => {
+                    let params = json::FileInfoParams {
+                        filename: filename.clone(),
+                    };
+                    let (id, msg) = json.request::<json::GetFileInfo>(&params);
+                    pending.insert(id, Pending::FileInfo { walk, filename });
+                    com.send(msg);
+                }
+            }
+        }
+    }
+
+    fn disconnected(
+        json: &Handler,
+        pending: &mut HashMap<u32, Pending>,
+        sync: &mut FileSync,
+        tx: &Subscribers,
+        shared: &Mutex<SharedData>,
+    ) {
+        {
+            let mut data = SharedData::lock(shared);
+            data.connected = false;
+            data.device = None;
+        }
+        for id in json.clear() {
+            match pending.remove(&id) {
+                Some(Pending::Request { reply, .. }) => reply(Err(Error::Disconnected)),
+                Some(
+                    Pending::Flash { walk }
+                    | Pending::FileList { walk, .. }
+                    | Pending::FileInfo { walk, .. },
+                ) if sync.abort(walk) => {
+                    Self::sync_status(SyncStatus::Disconnected, shared, tx);
+                }
+                _ => {}
+            }
+        }
+        tx.send(Event::Disconnected);
+    }
+
+    fn invalid(
+        error: String,
+        request: Option<Pending>,
+        sync: &mut FileSync,
+        tx: &Subscribers,
+        shared: &Mutex<SharedData>,
+    ) {
+        match request {
+            Some(Pending::Request { reply, .. }) => {
+                reply(Err(Error::InvalidResponse(error.clone())));
+            }
+            Some(
+                Pending::Flash { walk }
+                | Pending::FileList { walk, .. }
+                | Pending::FileInfo { walk, .. },
+            ) if sync.abort(walk) => {
+                Self::sync_status(SyncStatus::InvalidResponse, shared, tx);
+            }
+            _ => {}
+        }
+        tx.send(Event::ProtocolError(error));
+    }
+
+    // the lock is released before the event, so that callbacks may use the backend
+    fn sync_status(status: SyncStatus, shared: &Mutex<SharedData>, tx: &Subscribers) {
+        SharedData::lock(shared).sync_files = status;
+        tx.send(Event::FileSyncStatus);
+    }
+
+    fn sync_done(tracks: usize, shared: &Mutex<SharedData>, tx: &Subscribers) {
+        let tracks = u16::try_from(tracks).unwrap_or(u16::MAX);
+        Self::sync_status(SyncStatus::Done(tracks), shared, tx);
+    }
+
     fn handle_message(
         msg: Message,
-        _com: &com::Com,
-        _json: &Handler,
-        tx: &Sender<Event>,
-        mut data: MutexGuard<'_, SharedData>,
-        //database: &Database,
+        com: &com::Com,
+        json: &Handler,
+        tx: &Subscribers,
+        shared: &Mutex<SharedData>,
+        pending: &mut HashMap<u32, Pending>,
+        sync: &mut FileSync,
     ) {
         match msg {
-            Message::Response(resp) => match resp {
-                Response::InfoConnection(res) => match res {
-                    Ok(connection) => {
-                        let evt = Event::InfoConnection(Ok(Connection {
-                            mode: connection.mode,
-                        }));
-                        tx.send(evt).unwrap();
-                    }
-                    Err(e) => error!("Could not get InfoConnection: {e}"),
-                },
-                Response::InfoAbout(res) => match res {
-                    Ok(about) => {
-                        let evt = Event::InfoAbout(Ok(About {
-                            project: about.project,
-                            version: about.version,
-                            esp_idf: about.esp_idf,
-                        }));
-                        tx.send(evt).unwrap();
+            Message::Response(id, res) => match pending.remove(&id) {
+                Some(Pending::Request { parser, reply }) => match parser(res) {
+                    Ok(evt) => {
+                        reply(Ok(&evt));
+                        tx.send(evt);
                     }
-                    Err(e) => error!("Could not get InfoAbout: {e}"),
-                },
-                Response::InfoMemory(res) => match res {
-                    Ok(info) => {
-                        let evt = Event::InfoMemory(Ok(Memory {
-                            heap: Heap {
-                                allocated: info.heap.allocated,
-                                free: info.heap.free,
-                                minimum_free: info.heap.minimum_free,
-                            },
-                        }));
-                        tx.send(evt).unwrap();
+                    Err(e) => {
+                        let request = Pending::Request { parser, reply };
+                        Self::invalid(e, Some(request), sync, tx, shared);
                     }
-                    Err(e) => error!("Could not get InfoMemory: {e}"),
                 },
-                Response::InfoSPIFlash(res) => match res {
-                    Ok(info) => {
-                        let mut files = Vec::new();
-                        for f in info.files {
-                            files.push(File {
-                                name: f.name,
-                                content_type: f.content_type,
-                                size: f.size,
-                                md5: f.md5,
-                            });
+                Some(Pending::Flash { walk }) => {
+                    // without the md5s every listed track counts as changed
+                    let files = match json::GetInfoSPIFlash::result(res) {
+                        Ok(Ok(flash)) => flash.files,
+                        Ok(Err(e)) => {
+                            warn!("No md5s of the SPI flash: {e}");
+                            tx.send(Event::InfoSPIFlash(Err(e.into())));
+                            Vec::new()
                         }
-                        let evt = Event::InfoSPIFlash(Ok(SPIFlash {
-                            total: info.total,
-                            free: info.free,
-                            files,
-                        }));
-                        tx.send(evt).unwrap();
-                    }
-                    Err(e) => error!("Could not get InfoMemory: {e}"),
-                },
-                Response::ScanResult(res) => match res {
-                    Ok(list) => {
-                        let mut networks = Vec::new();
-                        for e in list {
-                            networks.push(Network {
-                                ssid: e.ssid,
-                                rssi: e.rssi,
-                            });
+                        Err(e) => {
+                            tx.send(Event::ProtocolError(e));
+                            Vec::new()
                         }
-                        let evt = Event::ScanResult(Ok(networks));
-                        tx.send(evt).unwrap();
+                    };
+                    if let Some(n) = sync.flash(walk, files) {
+                        Self::sync_done(n, shared, tx);
+                    } else {
+                        Self::sync_requests(sync, com, json, pending);
                     }
-                    Err(e) => {
-                        let evt = Event::ScanResult(Err(RemoteError {
-                            code: e.code,
-                            message: e.message,
-                        }));
-                        tx.send(evt).unwrap();
-                    }
-                },
-                Response::NetworkList(res) => match res {
-                    Ok(list) => {
-                        let mut networks = Vec::new();
-                        for e in list {
-                            networks.push(e.ssid);
+                }
+                Some(Pending::FileList { walk, dir }) => match json::GetFileList::result(res) {
+                    Ok(Ok(list)) => {
+                        let dirs = list.dirs.unwrap_or_default();
+                        let files: Vec<json::ListedFile> = list
+                            .files
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(Into::into)
+                            .collect();
+                        debug!(
+                            "Received {} dirs and {} files in '{dir}'",
+                            dirs.len(),
+                            files.len()
+                        );
+                        if let Some(n) = sync.listed(walk, &dir, dirs, files) {
+                            Self::sync_done(n, shared, tx);
+                        } else {
+                            Self::sync_requests(sync, com, json, pending);
                         }
-                        let evt = Event::NetworkList(Ok(networks));
-                        tx.send(evt).unwrap();
                     }
-                    Err(e) => {
-                        let evt = Event::NetworkList(Err(RemoteError {
-                            code: e.code,
-                            message: e.message,
-                        }));
-                        tx.send(evt).unwrap();
-                    }
-                },
-                Response::SetNetwork(res) => match res {
-                    Ok(_empty) => {
-                        let evt = Event::SetNetwork(Ok(()));
-                        tx.send(evt).unwrap();
-                    }
-                    Err(e) => {
-                        let evt = Event::SetNetwork(Err(RemoteError {
-                            code: e.code,
-                            message: e.message,
-                        }));
-                        tx.send(evt).unwrap();
-                    }
-                },
-                Response::DeleteNetwork(res) => match res {
-                    Ok(_empty) => {
-                        let evt = Event::DeleteNetwork(Ok(()));
-                        tx.send(evt).unwrap();
+                    Ok(Err(e)) => {
+                        if sync.abort(walk) {
+                            Self::sync_status(SyncStatus::Error(e.into()), shared, tx);
+                        }
                     }
                     Err(e) => {
-                        let evt = Event::DeleteNetwork(Err(RemoteError {
-                            code: e.code,
-                            message: e.message,
-                        }));
-                        tx.send(evt).unwrap();
+                        let request = Pending::FileList { walk, dir };
+                        Self::invalid(e, Some(request), sync, tx, shared);
                     }
                 },
-                Response::FileList(res) => match res {
-                    Ok(list) => {
-                        debug!(
-                            "Received {} dirs and {} files",
-                            list.dirs.map_or(0, |v| v.len()),
-                            list.files.map_or(0, |v| v.len()),
-                        );
-                        data.sync_files = SyncStatus::Done(0);
-                        let evt = Event::FileSyncStatus;
-                        tx.send(evt).unwrap();
-                    }
-                    Err(e) => {
-                        data.sync_files = SyncStatus::Error(RemoteError {
-                            code: e.code,
-                            message: e.message,
-                        });
-                        let evt = Event::FileSyncStatus;
-                        tx.send(evt).unwrap();
+                Some(Pending::FileInfo { walk, filename }) => {
+                    // a single unreadable track does not stop the sync
+                    let info = match json::GetFileInfo::result(res) {
+                        Ok(Ok(info)) => Some(info),
+                        Ok(Err(e)) => {
+                            warn!("No info for {filename}: {e}");
+                            tx.send(Event::FileInfoError(filename.clone(), e.into()));
+                            None
+                        }
+                        Err(e) => {
+                            tx.send(Event::ProtocolError(e));
+                            None
+                        }
+                    };
+                    if let Some(n) = sync.info(walk, &filename, info) {
+                        Self::sync_done(n, shared, tx);
+                    } else {
+                        Self::sync_requests(sync, com, json, pending);
                     }
-                },
+                }
+                None => {}
             },
-            /*RpcResult::FileList(lst) => {
-                  database.update_file_list(lst.files, lst.last);
-                  if lst.last {
-                      match database.get_unsynced_file() {
-                          Some(f) => {
-                              let p = database.sync_stats();
-                              tx.send(Event::Reload(Reload::Step(Some(p)))).unwrap();
-                              com.send(rpc.get_file_info(f));
-                          }
-                          None => {
-                              tx.send(Event::Reload(Reload::Stop)).unwrap();
-                          }
-                      }
-                  } else {
-                      tx.send(Event::Reload(Reload::Step(None))).unwrap();
-                      com.send(rpc.get_file_list(false));
-                  }
-              }
-              RpcResult::FileInfo(info) => {
-                  database.set_file_info(info);
-                  match database.get_unsynced_file() {
-                      Some(f) => {
-                          let p = database.sync_stats();
-                          tx.send(Event::Reload(Reload::Step(Some(p)))).unwrap();
-                          com.send(rpc.get_file_info(f));
-                      }
-                      None => {
-                          tx.send(Event::Reload(Reload::Stop)).unwrap();
-                          database.save();
-                      }
-                  }
-              }*/
-            /*match e.request {
-                ErrReq::Version => {}
-                ErrReq::FileList => {
-                    tx.send(Event::Reload(Reload::Stop)).unwrap();
+            Message::Invalid(id, e) => {
+                let request = id.and_then(|id| pending.remove(&id));
+                Self::invalid(e, request, sync, tx, shared);
+            }
+            Message::Notification(notification) => match notification {
+                Notification::Track(track) => {
+                    tx.send(Event::TrackChanged(track.file));
                 }
-                ErrReq::FileInfo => {
-                    tx.send(Event::Reload(Reload::Stop)).unwrap();
+                Notification::Volume(volume) => {
+                    tx.send(Event::VolumeChanged(volume.volume));
                 }
-                _ => {}
-            }*/
-            //Message::Notification => {}
+                Notification::Storage(storage) => {
+                    tx.send(Event::StorageChanged(Storage {
+                        total: storage.total,
+                        free: storage.free,
+                    }));
+                }
+            },
+        }
+    }
+}
+
+impl WithTimeout<'_> {
+    pub fn get_info_connection(&self) -> Result<RequestHandle<Connection>, Error> {
+        self.backend
+            .request::<json::GetInfoConnection>(&(), Some(self.timeout))
+    }
+
+    pub fn get_info_about(&self) -> Result<RequestHandle<About>, Error> {
+        self.backend
+            .request::<json::GetInfoAbout>(&(), Some(self.timeout))
+    }
+
+    pub fn get_info_memory(&self) -> Result<RequestHandle<Memory>, Error> {
+        self.backend
+            .request::<json::GetInfoMemory>(&(), Some(self.timeout))
+    }
+
+    pub fn get_info_spiflash(&self) -> Result<RequestHandle<SPIFlash>, Error> {
+        self.backend
+            .request::<json::GetInfoSPIFlash>(&(), Some(self.timeout))
+    }
+
+    pub fn get_wifi_scan_result(&self) -> Result<RequestHandle<Vec<Network>>, Error> {
+        self.backend
+            .request::<json::GetWifiScanResult>(&(), Some(self.timeout))
+    }
+
+    pub fn get_wifi_network_list(&self) -> Result<RequestHandle<Vec<String>>, Error> {
+        self.backend
+            .request::<json::GetWifiNetworkList>(&(), Some(self.timeout))
+    }
+
+    pub fn set_wifi_network(&self, ssid: String, key: String) -> Result<RequestHandle<()>, Error> {
+        self.backend.request::<json::SetWifiNetwork>(
+            &json::SetNetworkParams { ssid, key },
+            Some(self.timeout),
+        )
+    }
+
+    pub fn delete_wifi_network(&self, ssid: String) -> Result<RequestHandle<()>, Error> {
+        self.backend.request::<json::DeleteWifiNetwork>(
+            &json::DeleteNetworkParams { ssid },
+            Some(self.timeout),
+        )
+    }
+}
+
+impl<T> RequestHandle<T> {
+    pub fn try_get(&self) -> Option<Result<T, Error>> {
+        if self.done.get() {
+            return None;
+        }
+        let res = match self.receiver.try_recv() {
+            Ok(res) => res,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => Err(Error::BackendDead),
+        };
+        self.done.set(true);
+        Some(res)
+    }
+
+    pub fn wait(&self, timeout: Duration) -> Option<Result<T, Error>> {
+        if self.done.get() {
+            return None;
+        }
+        let res = match self.receiver.recv_timeout(timeout) {
+            Ok(res) => res,
+            Err(RecvTimeoutError::Timeout) => return None,
+            Err(RecvTimeoutError::Disconnected) => Err(Error::BackendDead),
+        };
+        self.done.set(true);
+        Some(res)
+    }
+}
+
+impl DiscoveredDevice {
+    pub fn is_compatible(&self) -> bool {
+        self.protocol_version.is_none_or(|v| v == PROTOCOL_VERSION)
+    }
+}
+
+impl From<common::jsonrpc::ExecError> for RemoteError {
+    fn from(e: common::jsonrpc::ExecError) -> Self {
+        Self {
+            code: e.code.into(),
+            message: e.message,
+        }
+    }
+}
+
+impl From<i16> for ErrorCode {
+    fn from(code: i16) -> Self {
+        match code {
+            -32700 => Self::ParseError,
+            -32600 => Self::InvalidRequest,
+            -32601 => Self::MethodNotFound,
+            -32602 => Self::InvalidParams,
+            -32603 => Self::InternalError,
+            -32099..=-32000 => Self::ServerError(code),
+            code => Self::Other(code),
+        }
+    }
+}
+
+impl From<ErrorCode> for i16 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::ParseError => -32700,
+            ErrorCode::InvalidRequest => -32600,
+            ErrorCode::MethodNotFound => -32601,
+            ErrorCode::InvalidParams => -32602,
+            ErrorCode::InternalError => -32603,
+            ErrorCode::ServerError(code) | ErrorCode::Other(code) => code,
         }
     }
 }
@@ -587,8 +1264,10 @@ impl Default for Backend {
 
 impl Drop for Backend {
     fn drop(&mut self) {
-        self.cmd_sender.send(Command::Quit).unwrap();
-        self.handle.take().unwrap().join().unwrap();
+        let _ = self.cmd_sender.send(Command::Quit);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
     }
 }
 
@@ -601,7 +1280,63 @@ impl fmt::Display for SyncStatus {
             Self::Aborted => write!(f, "Cancelled by user."),
             Self::Timeout => write!(f, "Timeout."),
             Self::Disconnected => write!(f, "Disconnected"),
-            Self::Error(e) => write!(f, "Error: {} [{}].", e.message, e.code),
+            Self::InvalidResponse => write!(f, "Invalid response."),
+            Self::Error(e) => write!(f, "Error: {} [{}].", e.message, i16::from(e.code)),
+        }
+    }
+}
+
+// a duration as seconds like in the config file
+#[cfg(feature = "serde")]
+mod secs {
+    use std::time::Duration;
+
+    use serde::Serializer;
+
+    pub(crate) use crate::config::secs as deserialize;
+
+    pub(crate) fn serialize<S: Serializer>(
+        duration: &Duration,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_code_round_trip() {
+        for code in [
+            -32700, -32600, -32601, -32602, -32603, -32099, -32000, -32001, -1, 0, 1,
+        ] {
+            assert_eq!(i16::from(ErrorCode::from(code)), code);
         }
     }
+
+    #[test]
+    fn error_code_ranges() {
+        assert_eq!(ErrorCode::from(-32601), ErrorCode::MethodNotFound);
+        assert_eq!(ErrorCode::from(-32000), ErrorCode::ServerError(-32000));
+        assert_eq!(ErrorCode::from(-32099), ErrorCode::ServerError(-32099));
+        assert_eq!(ErrorCode::from(-32100), ErrorCode::Other(-32100));
+        assert_eq!(ErrorCode::from(-31999), ErrorCode::Other(-31999));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn reconnecting_next_in_is_seconds() {
+        let state = ConnectionState::Reconnecting {
+            attempt: 2,
+            next_in: Duration::from_millis(1500),
+        };
+        let value = serde_json::json!({"reconnecting": {"attempt": 2, "next-in": 1.5}});
+        assert_eq!(serde_json::to_value(&state).unwrap(), value);
+        assert_eq!(
+            serde_json::from_value::<ConnectionState>(value).unwrap(),
+            state
+        );
+    }
 }
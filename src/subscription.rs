@@ -0,0 +1,191 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+
+use log::error;
+
+use crate::common::worker;
+use crate::{Event, EventKind};
+
+type Callback = Box<dyn Fn(&Event) + Send + Sync>;
+
+/// A callback registered with [`Backend::listen`](crate::Backend::listen), it is removed when
+/// this is dropped.
+pub struct Subscription {
+    id: u32,
+    subscribers: Weak<Subscribers>,
+}
+
+#[derive(Default)]
+pub(crate) struct Subscribers {
+    next_id: Mutex<u32>,
+    list: Mutex<Vec<Arc<Subscriber>>>,
+}
+
+struct Subscriber {
+    id: u32,
+    kinds: Option<Vec<EventKind>>,
+    sink: Sink,
+}
+
+enum Sink {
+    Channel(Sender<Event>),
+    Callback(Callback),
+}
+
+impl Subscribers {
+    pub(crate) fn channel(&self, kinds: Option<Vec<EventKind>>, sender: Sender<Event>) {
+        self.add(kinds, Sink::Channel(sender));
+    }
+
+    pub(crate) fn callback(
+        self: &Arc<Self>,
+        kinds: Option<Vec<EventKind>>,
+        callback: Callback,
+    ) -> Subscription {
+        Subscription {
+            id: self.add(kinds, Sink::Callback(callback)),
+            subscribers: Arc::downgrade(self),
+        }
+    }
+
+    pub(crate) fn send(&self, evt: Event) {
+        let kind = evt.kind();
+        // callbacks run without the lock, so that they may subscribe or unsubscribe themselves
+        let matching: Vec<Arc<Subscriber>> = lock(&self.list)
+            .iter()
+            .filter(|s| s.kinds.as_ref().is_none_or(|k| k.contains(&kind)))
+            .cloned()
+            .collect();
+        let mut closed = Vec::new();
+        for s in matching {
+            match &s.sink {
+                Sink::Channel(sender) => {
+                    // the receiver has been dropped
+                    if sender.send(evt.clone()).is_err() {
+                        closed.push(s.id);
+                    }
+                }
+                Sink::Callback(callback) => {
+                    // a panic of the application must not take down the backend thread
+                    let call = panic::catch_unwind(AssertUnwindSafe(|| callback(&evt)));
+                    if let Err(payload) = call {
+                        let msg = worker::message(&*payload);
+                        error!("event callback panicked and is removed: {msg}");
+                        closed.push(s.id);
+                    }
+                }
+            }
+        }
+        if !closed.is_empty() {
+            lock(&self.list).retain(|s| !closed.contains(&s.id));
+        }
+    }
+
+    fn add(&self, kinds: Option<Vec<EventKind>>, sink: Sink) -> u32 {
+        let mut next_id = lock(&self.next_id);
+        let id = *next_id;
+        *next_id += 1;
+        lock(&self.list).push(Arc::new(Subscriber { id, kinds, sink }));
+        id
+    }
+
+    fn remove(&self, id: u32) {
+        lock(&self.list).retain(|s| s.id != id);
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(subscribers) = self.subscribers.upgrade() {
+            subscribers.remove(self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::mpsc;
+
+    use super::*;
+
+    fn count(
+        subscribers: &Arc<Subscribers>,
+        kinds: Option<Vec<EventKind>>,
+    ) -> (Arc<AtomicU32>, Subscription) {
+        let calls = Arc::new(AtomicU32::new(0));
+        let counter = calls.clone();
+        let subscription = subscribers.callback(
+            kinds,
+            Box::new(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }),
+        );
+        (calls, subscription)
+    }
+
+    #[test]
+    fn filters_by_kind() {
+        let subscribers = Arc::new(Subscribers::default());
+        let (sender, receiver) = mpsc::channel();
+        subscribers.channel(Some(vec![EventKind::VolumeChanged]), sender);
+        let (all, _all) = count(&subscribers, None);
+        subscribers.send(Event::Connected);
+        subscribers.send(Event::VolumeChanged(3));
+        assert!(matches!(
+            receiver.try_iter().collect::<Vec<_>>()[..],
+            [Event::VolumeChanged(3)]
+        ));
+        assert_eq!(all.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn unsubscribes_on_drop() {
+        let subscribers = Arc::new(Subscribers::default());
+        let (calls, subscription) = count(&subscribers, None);
+        subscribers.send(Event::Connected);
+        drop(subscription);
+        subscribers.send(Event::Connected);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let (sender, receiver) = mpsc::channel();
+        subscribers.channel(None, sender);
+        drop(receiver);
+        subscribers.send(Event::Connected);
+        assert!(lock(&subscribers.list).is_empty());
+    }
+
+    #[test]
+    fn removes_panicking_callback() {
+        let subscribers = Arc::new(Subscribers::default());
+        let _panics = subscribers.callback(None, Box::new(|_| panic!("callback")));
+        let (calls, _subscription) = count(&subscribers, None);
+        subscribers.send(Event::Connected);
+        subscribers.send(Event::Connected);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(lock(&subscribers.list).len(), 1);
+    }
+
+    #[test]
+    fn callback_may_subscribe() {
+        let subscribers = Arc::new(Subscribers::default());
+        let inner = Arc::new(Mutex::new(Vec::new()));
+        let (list, weak) = (inner.clone(), Arc::downgrade(&subscribers));
+        let _subscription = subscribers.callback(
+            Some(vec![EventKind::Connected]),
+            Box::new(move |_| {
+                let subscribers = weak.upgrade().unwrap();
+                lock(&list).push(count(&subscribers, None));
+            }),
+        );
+        subscribers.send(Event::Connected);
+        subscribers.send(Event::Disconnected);
+        let list = lock(&inner);
+        assert_eq!(list[0].0.load(Ordering::SeqCst), 1);
+    }
+}